@@ -1,108 +1,166 @@
+#[cfg(not(target_os = "linux"))]
 use std::process::{Command, Child, Stdio, ChildStdout};
+#[cfg(not(target_os = "linux"))]
 use std::io::{BufReader, BufRead};
+use anyhow::Result;
 use dns_lookup::lookup_host;
+#[cfg(not(target_os = "linux"))]
+use pinger::traceroute::{Hop, TracerouteParser};
+#[cfg(target_os = "linux")]
+use pinger::{route, trace::Tracer};
+#[cfg(target_os = "linux")]
+use std::net::IpAddr;
 
+/// A well known anycast address that's close to most places, pinged when tracing doesn't find
+/// enough hops.
+pub const FALLBACK_TARGET: &str = "1.1.1.1";
+
+#[cfg(not(target_os = "linux"))]
 struct TracertIter {
     trace_route : Child,
-    trace_output : BufReader<ChildStdout>
+    trace_output : BufReader<ChildStdout>,
+    parser : TracerouteParser,
+    done : bool
 }
 
+#[cfg(not(target_os = "linux"))]
 impl TracertIter {
-    fn new() -> TracertIter {
-        let mut trace = if cfg!(target_os = "windows") {
-            Command::new("cmd")
-                .args(&["/C", "tracert -d google.com"])
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute process")
+    fn new(target: &str) -> Result<TracertIter> {
+        let (program, args) = if cfg!(target_os = "windows") {
+            ("cmd", vec!["/C", "tracert", "-d", target])
         } else {
-            Command::new("sh")
-                .arg("-c")
-                .arg("traceroute -n google.com")
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute process")
+            ("traceroute", vec!["-n", target])
         };
-        let mut output = BufReader::new(trace.stdout.take().unwrap());
-        
-        // skip unimportant lines
-        let mut junk = Vec::new();
-        
-        if cfg!(target_os = "windows")
-        {
-            //4 junk lines in windows
-            for _ in 0..4
-            {
-                output.read_until(b'\n',&mut junk).unwrap();
-                junk.clear();
-            }
-        } else
-        {
-            //1 junk line in linux/MacOS
-            output.read_until(b'\n',&mut junk).unwrap();
-            junk.clear();
-        }
+        let mut trace = Command::new(program)
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("could not run {}: {}", program, e))?;
+        let output = BufReader::new(trace.stdout.take().unwrap());
         
-        
-        TracertIter{trace_route: trace, trace_output: output}
+        Ok(TracertIter{trace_route: trace, trace_output: output, parser: TracerouteParser::default(), done: false})
+    }
+}
+
+// Stop tracing once we have enough hops, rather than leaving traceroute running
+#[cfg(not(target_os = "linux"))]
+impl Drop for TracertIter {
+    fn drop(&mut self) {
+        let _ = self.trace_route.kill();
+        let _ = self.trace_route.wait();
     }
 }
 
+#[cfg(not(target_os = "linux"))]
 impl Iterator for TracertIter {
-    type Item = Option<String>;
+    type Item = Hop;
     
-    // Some(None) indicates that the hop didn't respond
+    // The headers and footers are skipped by the parser
     fn next(&mut self) -> Option<Self::Item> {
-        let mut line_raw = Vec::new();
-        let len = self.trace_output.read_until(b'\n',&mut line_raw).unwrap();
-        if len == 0 { return None; }
-        let line = String::from_utf8_lossy(&line_raw).into_owned();
-        
-        let hop_addr: &str;
-
-        if cfg!(target_os = "windows")
-        {
-            hop_addr = if let Some(a) = line.split_whitespace().nth(7) {
-            a
-            } else {
-                return Some(None);
-            };
-        } else
-        {
-            hop_addr = if let Some(a) = line.split_whitespace().nth(1) {
-            a
-            } else {
-                return Some(None);
-            };
+        while !self.done {
+            let mut line_raw = Vec::new();
+            let len = self.trace_output.read_until(b'\n',&mut line_raw).unwrap_or(0);
+            if len == 0 { self.done = true; break; }
+            let line = String::from_utf8_lossy(&line_raw);
+            if let Some(hop) = self.parser.parse(&line) { return Some(hop); }
         }
-        
-        // hop_addr might be a localized error message (eg. a timeout) we try to
-        // do a lookup to test this
-        if lookup_host(hop_addr).is_err() { return Some(None); }
-        Some(Some(hop_addr.to_owned()))
+        self.parser.finish()
     }
 }
 
-// The first host is the first responding address returned by tracert.
-// The 2nd and 3rd hosts are the next two _public_ hosts returned by tracert.
-// non-responing hosts will be skipped.
-pub fn get_desired_hops() -> [String;3] {
-    let mut iter = TracertIter::new();
-    
-    let first = loop {
-        let host_maybe = if let Some(x) = iter.next() { x } else { panic!("unexpected end of tracert output"); };
-        if host_maybe.is_some() { break host_maybe.unwrap(); }
-    };
+// The address that answered for each hop, if any. Linux sends the probes itself, so that
+// traceroute doesn't need to be installed.
+#[cfg(target_os = "linux")]
+fn hops(target: &str) -> Result<impl Iterator<Item = Option<String>>> {
+    Ok(Tracer::default()
+        .start(target)?
+        .map(|hop| hop.responders.first().map(|addr| addr.to_string())))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hops(target: &str) -> Result<impl Iterator<Item = Option<String>>> {
+    Ok(TracertIter::new(target)?.map(|hop| hop.responders().first().map(|addr| addr.to_string())))
+}
+
+// The first host is the default gateway from the routing table, or if that can't be found the
+// first responding address returned by tracert.
+// The rest are the next `count - 1` _public_ hosts returned by tracert, or any responding hosts
+// with `include_private`.
+// non-responing hosts will be skipped, so fewer than `count` hosts are returned if not enough
+// responded before the end of the trace.
+pub fn get_desired_hops(target: &str, count: usize, include_private: bool) -> Result<Vec<String>> {
+    let mut found = Vec::with_capacity(count);
+    found.extend(gateway());
+    if found.len() >= count { return Ok(found); }
     
-    let mut public_ips = Vec::with_capacity(2);
-    for host_maybe in iter {
-        if host_maybe.is_none() { continue; }
-        let host = host_maybe.unwrap();
-        if !lookup_host(&host).unwrap()[0].is_global() { continue; }
-        public_ips.push(host);
-        if public_ips.len() == 2 { break; }
+    for host in hops(target)?.flatten() {
+        if found.len() >= count { break; }
+        // The gateway answers the trace too
+        if found.contains(&host) { continue; }
+        if !found.is_empty() && !include_private && !is_global(&host) { continue; }
+        found.push(host);
     }
-    if public_ips.len() < 2 { panic!("unexpected end of tracert output"); }
     
-    [first, public_ips[0].clone(), public_ips[1].clone()]
+    Ok(found)
+}
+
+fn is_global(host: &str) -> bool {
+    match lookup_host(host) {
+        Ok(addrs) => addrs.first().is_some_and(|addr| addr.is_global()),
+        Err(_) => false,
+    }
+}
+
+// Makes do with the hops that were found: the gateway from the routing table stands in if
+// tracing didn't find one, then the fallback target is added.
+pub fn fallback_hops(mut hops: Vec<String>) -> Vec<String> {
+    if hops.is_empty() {
+        hops.extend(gateway());
+    }
+    if !hops.iter().any(|hop| hop == FALLBACK_TARGET) {
+        hops.push(FALLBACK_TARGET.to_string());
+    }
+    hops
+}
+
+// The default gateway, read straight from the kernel. Link local IPv6 gateways can only be
+// pinged through the interface they're on, so it's given with them.
+#[cfg(target_os = "linux")]
+fn gateway() -> Option<String> {
+    let route = route::default_route()?;
+    match route.gateway {
+        IpAddr::V6(gateway) if gateway.segments()[0] & 0xffc0 == 0xfe80 => {
+            Some(format!("{}%{}", gateway, route.interface))
+        }
+        gateway => Some(gateway.to_string()),
+    }
+}
+
+// The default gateway, from the route to 0.0.0.0 in
+// "          0.0.0.0          0.0.0.0      192.168.1.1    192.168.1.100     25"
+#[cfg(windows)]
+fn gateway() -> Option<String> {
+    let output = Command::new("route").args(&["print", "-4", "0.0.0.0"]).output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            // The gateway is "On-link" for routes that don't have one
+            ["0.0.0.0", "0.0.0.0", gateway, ..] => {
+                gateway.parse::<std::net::Ipv4Addr>().ok().map(|gateway| gateway.to_string())
+            }
+            _ => None,
+        }
+    })
+}
+
+// The default gateway, from "    gateway: 192.168.1.1" on macOS and the BSDs
+#[cfg(not(any(target_os = "linux", windows)))]
+fn gateway() -> Option<String> {
+    let output = Command::new("route").args(&["-n", "get", "default"]).output().ok()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("gateway:"))
+        .map(|gateway| gateway.trim().to_string())
 }
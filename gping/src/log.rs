@@ -9,10 +9,10 @@ pub struct CsvLogger {
 	buffers: Vec<Vec<Duration>>,
 	rows_written: usize,
 	num_targets: usize,
-	
+	interval: Duration,
 }
 impl CsvLogger {
-	pub fn new(num_targets: usize) -> Self {
+	pub fn new(num_targets: usize, interval: Duration) -> Self {
 		let mut i : u32 = 1;
 		let mut p = PathBuf::new();
 		loop {
@@ -42,6 +42,7 @@ impl CsvLogger {
 			buffers: vec![Vec::new();num_targets],
 			rows_written: 0,
 			num_targets,
+			interval,
 		}
 	}
 	
@@ -56,10 +57,10 @@ impl CsvLogger {
 		if !row_complete { return; }
 		
 		// We don't use floating point types here since they cause ugly presicion errors.
-		let time_decisecs = self.rows_written * 2;
-		let lower = time_decisecs % 10;
-		let upper = time_decisecs / 10;
-		self.file.as_mut().unwrap().write_all(&format!("{}.{},", upper, lower).as_bytes()).unwrap();
+		let time_millis = self.rows_written as u128 * self.interval.as_millis();
+		let lower = time_millis % 1000;
+		let upper = time_millis / 1000;
+		self.file.as_mut().unwrap().write_all(format!("{}.{:03},", upper, lower).as_bytes()).unwrap();
 		
 		for buf in &self.buffers {
 			let duration = buf[self.rows_written];
@@ -1,6 +1,7 @@
 #![feature(ip)]
 
 use crate::plot_data::PlotData;
+use crate::target::Target;
 use anyhow::{anyhow, Result};
 use chrono::prelude::*;
 use crossterm::event::{KeyEvent, KeyModifiers};
@@ -10,14 +11,18 @@ use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use dns_lookup::lookup_host;
-use pinger::{ping, PingResult};
+use itertools::Itertools;
+use pinger::http::Phases;
+use pinger::replay::ReplayPinger;
+use pinger::{PingOptions, PingResult};
 use std::io;
+use std::io::Write;
 use std::iter;
 use std::net::IpAddr;
 use std::ops::Add;
 use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
 use std::sync::{mpsc, Arc};
 use std::thread;
 use std::thread::JoinHandle;
@@ -33,6 +38,7 @@ use tui::Terminal;
 mod plot_data;
 mod find_hops;
 mod log;
+mod target;
 
 const HOP_COLORS : [Color;3] = [
     Color::White,
@@ -56,7 +62,7 @@ struct Args {
     )]
     watch_interval: f32,
     #[structopt(
-        help = "Hosts or IPs to ping, or commands to run if --cmd is provided."
+        help = "Hosts or IPs to ping, tcp://host:port to time TCP connections to, udp://host to time probes echoed by netcheck-reflector, http(s):// URLs to time requests to, dns://resolver/name to time queries to, or commands to run if --cmd is provided."
     )]
     hosts_or_commands: Vec<String>,
     #[structopt(
@@ -75,6 +81,24 @@ struct Args {
     
     #[structopt(short = "s", long, help = "Uses dot characters instead of braille. Enabled by default on Windows.")]
     simple_graphics: bool,
+    #[structopt(long, help = "Stacks the DNS, connect, TLS and time to first byte phases of http(s):// targets in the graph.")]
+    phases: bool,
+    #[structopt(long, help = "Replays a ping transcript or a CSV log written by a previous run instead of pinging, graphing each target recorded in it.")]
+    replay: Option<String>,
+    #[structopt(long, default_value = "1", help = "How many times faster than it was recorded to replay.")]
+    replay_speed: f64,
+    #[structopt(long, help = "The ping program to run instead of the one on the PATH, e.g. /usr/bin/ping6.")]
+    ping_program: Option<String>,
+    #[structopt(long, help = "A command to run ping under, e.g. 'ip netns exec blue'.")]
+    ping_prefix: Option<String>,
+    #[structopt(long = "ping-arg", allow_hyphen_values = true, number_of_values = 1, help = "An extra argument to pass to ping, after the ones gping passes. Can be given more than once.")]
+    ping_args: Vec<String>,
+    #[structopt(long, default_value = "google.com", help = "Where to trace the route to when no hosts are given, to find the hops to ping.")]
+    trace_target: String,
+    #[structopt(long, default_value = "3", help = "How many hops to ping when no hosts are given, starting with the gateway.")]
+    hops: usize,
+    #[structopt(long, help = "Pings hops with private addresses too when no hosts are given. Only the gateway is pinged otherwise.")]
+    private_hops: bool,
 }
 
 struct App {
@@ -164,17 +188,33 @@ impl App {
 
 #[derive(Debug)]
 enum Update {
-    Result(Duration),
+    Result(Duration, Option<Phases>),
+    Late,
     Timeout,
+    Failed,
     Unknown,
+    Warning(String),
+    Error(String),
 }
 
 impl From<PingResult> for Update {
     fn from(result: PingResult) -> Self {
         match result {
-            PingResult::Pong(duration, _) => Update::Result(duration),
+            // Duplicates would skew the stats, and late replies were already counted as timeouts
+            PingResult::Pong(_, reply) if reply.duplicate => Update::Unknown,
+            PingResult::Pong(_, reply) if reply.late => Update::Late,
+            PingResult::Pong(duration, reply) => Update::Result(duration, reply.phases),
             PingResult::Timeout(_) => Update::Timeout,
-            PingResult::Unknown(_) => Update::Unknown,
+            PingResult::Unreachable(_)
+            | PingResult::TtlExceeded(_)
+            | PingResult::HttpError(..)
+            | PingResult::DnsError(..) => Update::Failed,
+            // A redirect doesn't stop the echo request from being answered
+            PingResult::Redirect(_) | PingResult::Summary(_) | PingResult::Unknown(_) => {
+                Update::Unknown
+            }
+            PingResult::Warning(warning) => Update::Warning(warning),
+            PingResult::Error(..) => Update::Error(result.to_string()),
         }
     }
 }
@@ -217,7 +257,7 @@ fn start_cmd_thread(
             let status = child.wait()?;
             let duration = start.elapsed();
             let update = if status.success() {
-                Update::Result(duration)
+                Update::Result(duration, None)
             } else {
                 Update::Timeout
             };
@@ -229,17 +269,29 @@ fn start_cmd_thread(
 }
 
 fn start_ping_thread(
-    host: String,
+    target: Target,
+    options: PingOptions,
     host_id: usize,
     ping_tx: Sender<Event>,
     kill_event: Arc<AtomicBool>,
 ) -> JoinHandle<Result<()>> {
     // Pump ping messages into the queue
     thread::spawn(move || -> Result<()> {
-        let stream = ping(host)?;
+        let mut stream = match target.start(options) {
+            Ok(stream) => stream,
+            Err(e) => {
+                ping_tx.send(Event::Update(host_id, Update::Error(e.to_string())))?;
+                return Ok(());
+            }
+        };
         while !kill_event.load(Ordering::Acquire) {
-            ping_tx.send(Event::Update(host_id, stream.recv()?.into()))?;
+            match stream.recv_timeout(Duration::from_millis(100)) {
+                Ok(result) => ping_tx.send(Event::Update(host_id, result.into()))?,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
         }
+        stream.stop();
         Ok(())
     })
 }
@@ -267,17 +319,65 @@ fn get_host_ipaddr(host: &str, force_ipv4: bool, force_ipv6: bool) -> Result<Str
     Ok(ipaddr?.to_string())
 }
 
+// What the minimap calls the nth stop on the way, where 0 is this device.
+fn hop_name(n: usize) -> String {
+    match n {
+        0 => "".to_owned(),
+        1 => "Home Gateway".to_owned(),
+        n => format!("Internet Hop {}", n - 1),
+    }
+}
+
+// How every target is pinged. Only hosts use the options that are about running ping.
+fn ping_options(args: &Args) -> PingOptions {
+    let prefix = args.ping_prefix.as_deref().unwrap_or_default();
+    let mut options = PingOptions::new()
+        .interval(Duration::from_millis((args.watch_interval * 1000.0).round() as u64))
+        .prefix(prefix.split_whitespace())
+        .extra_args(args.ping_args.iter().cloned());
+    if let Some(program) = &args.ping_program {
+        options = options.program(program.clone());
+    }
+    options
+}
+
 fn main() -> Result<()> {
     let mut args = Args::from_args();
     
     #[cfg(target_os="windows")]
     {args.simple_graphics = true;}
     
+    if let Some(replay) = &args.replay {
+        args.hosts_or_commands = ReplayPinger::targets(replay)?;
+    }
+
     let enable_map = if args.hosts_or_commands.len() == 0 {
-        print!("no hosts given, pinging the desired three hosts determined by tracert... : ");
-        let hops = find_hops::get_desired_hops();
+        if args.hops == 0 {
+            return Err(anyhow!("--hops must be at least 1"));
+        }
+        print!(
+            "no hosts given, pinging the desired {} hosts determined by tracing the route to {}... : ",
+            args.hops, args.trace_target
+        );
+        io::stdout().flush()?;
+        let found = find_hops::get_desired_hops(&args.trace_target, args.hops, args.private_hops);
+        let hops = match found {
+            Ok(hops) if hops.len() == args.hops => hops,
+            found => {
+                match &found {
+                    Err(e) => println!("\ncould not trace the route to {}: {}", args.trace_target, e),
+                    Ok(hops) => println!("\nonly {} of the {} hops were found", hops.len(), args.hops),
+                }
+                print!(
+                    "falling back to {} along with the gateway, if it can be found... : ",
+                    find_hops::FALLBACK_TARGET
+                );
+                io::stdout().flush()?;
+                find_hops::fallback_hops(found.unwrap_or_default())
+            }
+        };
         args.hosts_or_commands.extend_from_slice(&hops);
-        println!("{}, {}, {}", hops[0], hops[1], hops[2]);
+        println!("{}", hops.join(", "));
         true
     } else {
         true
@@ -286,12 +386,12 @@ fn main() -> Result<()> {
     let mut data = vec![];
 
     for (idx, host_or_cmd) in args.hosts_or_commands.iter().enumerate() {
-        let display = match args.cmd {
+        let display = match args.cmd || args.replay.is_some() {
             true => host_or_cmd.to_string(),
             false => format!(
                 "{} ({})",
                 host_or_cmd,
-                get_host_ipaddr(host_or_cmd, args.ipv4, args.ipv6)?
+                get_host_ipaddr(Target::parse(host_or_cmd)?.host(), args.ipv4, args.ipv6)?
             ),
         };
         
@@ -328,6 +428,7 @@ fn main() -> Result<()> {
     let mut threads = vec![];
 
     let killed = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let options = ping_options(&args);
 
     for (host_id, host_or_cmd) in args.hosts_or_commands.iter().cloned().enumerate() {
         if args.cmd {
@@ -339,9 +440,23 @@ fn main() -> Result<()> {
                 std::sync::Arc::clone(&killed),
             );
             threads.push(cmd_thread);
+        } else if let Some(replay) = &args.replay {
+            threads.push(start_ping_thread(
+                Target::Replay {
+                    name: host_or_cmd,
+                    path: replay.clone(),
+                    column: host_id,
+                    speed: args.replay_speed,
+                },
+                options.clone(),
+                host_id,
+                key_tx.clone(),
+                std::sync::Arc::clone(&killed),
+            ));
         } else {
             threads.push(start_ping_thread(
-                host_or_cmd,
+                Target::parse(&host_or_cmd)?,
+                options.clone(),
                 host_id,
                 key_tx.clone(),
                 std::sync::Arc::clone(&killed),
@@ -363,7 +478,11 @@ fn main() -> Result<()> {
     });
     threads.push(key_thread);
     
-    let mut logger = log::CsvLogger::new(args.hosts_or_commands.len());
+    // ping may be sending less often than asked, in which case the rows are further apart
+    let pinging = !args.cmd && args.replay.is_none() && args.hosts_or_commands.iter()
+        .any(|target| matches!(Target::parse(target), Ok(Target::Icmp(_))));
+    let log_interval = if pinging { pinger::effective_interval(&options) } else { options.interval };
+    let mut logger = log::CsvLogger::new(args.hosts_or_commands.len(), log_interval);
     
     let mut rolling_buffers : Vec<VecDeque<(Instant,Duration)>> = vec![VecDeque::new(); args.hosts_or_commands.len()];
     
@@ -371,18 +490,28 @@ fn main() -> Result<()> {
         match rx.recv()? {
             Event::Update(host_id, update) => {
                 match update {
-                    Update::Result(duration) => {
+                    Update::Result(duration, phases) => {
                         if enable_map {
                             rolling_buffers[host_id].push_back((Instant::now(),duration));
                         }
                         app.update(host_id, duration);
+                        if let (true, Some(phases)) = (args.phases, phases) {
+                            app.data[host_id].update_phases(phases);
+                        }
                         logger.log(host_id, duration);
                     },
                     Update::Timeout => {
                         app.update(host_id, Duration::from_secs(1));
                         logger.log(host_id, Duration::from_secs(1));
                     },
+                    Update::Late => app.data[host_id].update_late(),
+                    Update::Failed => {
+                        app.data[host_id].update_error();
+                        logger.log(host_id, Duration::from_secs(1));
+                    },
                     Update::Unknown => (),
+                    Update::Warning(warning) => app.data[host_id].set_warning(warning),
+                    Update::Error(error) => app.data[host_id].set_error(error),
                 };
                 terminal.draw(|f| {
                     // Split our
@@ -418,18 +547,15 @@ fn main() -> Result<()> {
                     let chart_chunk = chunks[total_chunks - n].to_owned();
 
                     for (plot_data, chunk) in app.data.iter().zip(header_chunks) {
+                        // Errors take up the space the stats would have used
+                        let constraints = if plot_data.error.is_some() {
+                            vec![Constraint::Percentage(20), Constraint::Percentage(80)]
+                        } else {
+                            vec![Constraint::Percentage(20); 5]
+                        };
                         let header_layout = Layout::default()
                             .direction(Direction::Horizontal)
-                            .constraints(
-                                [
-                                    Constraint::Percentage(20),
-                                    Constraint::Percentage(20),
-                                    Constraint::Percentage(20),
-                                    Constraint::Percentage(20),
-                                    Constraint::Percentage(20),
-                                ]
-                                .as_ref(),
-                            )
+                            .constraints(constraints)
                             .split(chunk);
 
                         for (area, paragraph) in
@@ -439,13 +565,29 @@ fn main() -> Result<()> {
                         }
                     }
 
-                    let datasets: Vec<Dataset> = app.data.iter().map(|d| d.into()).collect();
+                    let datasets: Vec<Dataset> = app
+                        .data
+                        .iter()
+                        .flat_map(|d| d.phase_datasets().into_iter().chain(iter::once(d.into())))
+                        .collect();
 
                     let y_axis_bounds = app.y_axis_bounds();
                     let x_axis_bounds = app.x_axis_bounds();
 
+                    // Every target tends to get the same warning, so each is only shown once
+                    let warnings = app
+                        .data
+                        .iter()
+                        .filter_map(|d| d.warning.as_deref())
+                        .unique()
+                        .join("; ");
+
                     let chart = Chart::new(datasets)
-                        .block(Block::default().borders(Borders::NONE))
+                        .block(
+                            Block::default()
+                                .borders(Borders::NONE)
+                                .title(Span::styled(warnings, Style::default().fg(Color::Yellow))),
+                        )
                         .x_axis(
                             Axis::default()
                                 .style(Style::default().fg(Color::Gray))
@@ -468,7 +610,8 @@ fn main() -> Result<()> {
                         let map_inner = map_box.inner(map_chunk);
                         f.render_widget(map_box, map_chunk);
                         
-                        let extra_chunk_width = args.hosts_or_commands.last().unwrap().len().max("Internet Hop 2".len()) as u16;
+                        let last_hop = hop_name(args.hosts_or_commands.len());
+                        let extra_chunk_width = args.hosts_or_commands.last().unwrap().len().max(last_hop.len()) as u16;
                         let width = map_inner.width;
                         if width <= extra_chunk_width { return; }
                         let remaining_width = width - extra_chunk_width;
@@ -482,7 +625,6 @@ fn main() -> Result<()> {
                                 .take(num_normal_chunks as usize)
                                 .chain(iter::once(Constraint::Length(extra_chunk_width)))
                                 .collect::<Vec<_>>()
-                                .as_ref()
                             )
                             .split(map_inner);
                         
@@ -492,11 +634,7 @@ fn main() -> Result<()> {
                             .zip(subchunks.clone())
                             .enumerate()
                         {
-                            let name = match i {
-                                0 => "".to_owned(),
-                                1 => "Home Gateway".to_owned(),
-                                n => format!("Internet Hop {}",n-1)
-                            };
+                            let name = hop_name(i);
                             
                             let mut line2 = chunk.clone();
                             line2.y += 1;
@@ -583,9 +721,7 @@ fn main() -> Result<()> {
                         extra_chunk2.y += 1;
                         extra_chunk2.height -= 1;
                         
-                        let n = subchunks.len() -1;
-                        let name = format!("Internet Hop {}",n-1);
-                        f.render_widget(Block::default().title(Span::raw(name)), extra_chunk);
+                        f.render_widget(Block::default().title(Span::raw(last_hop)), extra_chunk);
                         f.render_widget(Block::default().title(Span::raw(args.hosts_or_commands.last().unwrap())), extra_chunk2);
                     }
                 })?;
@@ -1,14 +1,36 @@
 use chrono::prelude::*;
 use core::time::Duration;
 use itertools::Itertools;
-use tui::style::Style;
+use pinger::http::Phases;
+use tui::style::{Color, Style};
 use tui::symbols;
 use tui::widgets::{Dataset, GraphType, Paragraph};
 
+// Names and colours for the stacked phases of HTTP requests, from the bottom up
+const PHASES: [(&str, Color); 4] = [
+    ("dns", Color::Blue),
+    ("connect", Color::Green),
+    ("tls", Color::Yellow),
+    ("first byte", Color::Red),
+];
+
 pub struct PlotData {
     pub display: String,
     pub data: Vec<(f64, f64)>,
     pub style: Style,
+    /// Set when pinging has failed, in which case it's shown instead of the stats.
+    pub error: Option<String>,
+    /// Set when pinging carries on differently to how it was asked to, e.g. more slowly.
+    pub warning: Option<String>,
+    /// When each error (ICMP unreachable or TTL exceeded, an HTTP error status or a failed DNS
+    /// query) was received.
+    /// These aren't plotted, and are counted apart from timeouts.
+    errors: Vec<f64>,
+    /// Where each phase of an HTTP request ended, stacked on top of the ones before it. Empty
+    /// unless phases are being graphed.
+    phases: Vec<Vec<(f64, f64)>>,
+    /// When each late reply (one that arrived after being counted as a timeout) was received.
+    late: Vec<f64>,
     buffer: chrono::Duration,
     simple_graphics: bool,
 }
@@ -19,6 +41,11 @@ impl PlotData {
             display,
             data: Vec::with_capacity(150), // ringbuffer::FixedRingBuffer::new(capacity),
             style,
+            error: None,
+            warning: None,
+            errors: Vec::new(),
+            phases: Vec::new(),
+            late: Vec::new(),
             buffer: chrono::Duration::seconds(buffer as i64),
             simple_graphics,
         }
@@ -39,10 +66,69 @@ impl PlotData {
         if let Some(idx) = last_idx {
             self.data.drain(0..idx).for_each(drop)
         }
+        self.errors.retain(|timestamp| *timestamp >= earliest_timestamp);
+        self.late.retain(|timestamp| *timestamp >= earliest_timestamp);
+        for series in self.phases.iter_mut() {
+            series.retain(|(timestamp, _)| *timestamp >= earliest_timestamp);
+        }
+    }
+
+    /// Adds the phases of a request, which should already have been added with `update`.
+    pub fn update_phases(&mut self, phases: Phases) {
+        let idx = Local::now().timestamp_millis() as f64 / 1_000f64;
+        let durations = [
+            phases.dns,
+            phases.connect,
+            phases.tls.unwrap_or_default(),
+            phases.first_byte,
+        ];
+        self.phases.resize(durations.len(), Vec::new());
+        let mut end = Duration::default();
+        for (series, duration) in self.phases.iter_mut().zip(durations.iter()) {
+            end += *duration;
+            series.push((idx, end.as_micros() as f64));
+        }
+    }
+
+    pub fn phase_datasets(&self) -> Vec<Dataset<'_>> {
+        self.phases
+            .iter()
+            .zip(PHASES.iter())
+            .map(|(series, (name, color))| {
+                Dataset::default()
+                    .name(*name)
+                    .marker(self.marker())
+                    .style(Style::default().fg(*color))
+                    .graph_type(GraphType::Line)
+                    .data(series.as_slice())
+            })
+            .collect()
+    }
+
+    pub fn update_error(&mut self) {
+        record_event(&mut self.errors, self.buffer);
+    }
+
+    pub fn update_late(&mut self) {
+        record_event(&mut self.late, self.buffer);
+    }
+
+    pub fn set_error(&mut self, error: String) {
+        self.error = Some(error);
+    }
+
+    pub fn set_warning(&mut self, warning: String) {
+        self.warning = Some(warning);
     }
 
     pub fn header_stats(&self) -> Vec<Paragraph> {
         let ping_header = Paragraph::new(self.display.clone()).style(self.style);
+        if let Some(error) = &self.error {
+            return vec![
+                ping_header,
+                Paragraph::new(error.clone()).style(self.style.fg(Color::Red)),
+            ];
+        }
         let items: Vec<&f64> = self
             .data
             .iter()
@@ -50,7 +136,13 @@ impl PlotData {
             .map(|(_, v)| v)
             .collect();
         if items.is_empty() {
-            return vec![ping_header];
+            if self.errors.is_empty() {
+                return vec![ping_header];
+            }
+            return vec![
+                ping_header,
+                Paragraph::new(format!("err# {:?}", self.errors.len())).style(self.style),
+            ];
         }
 
         let min = **items.first().unwrap();
@@ -71,20 +163,38 @@ impl PlotData {
                 .style(self.style),
             Paragraph::new(format!("p95 {:?}", Duration::from_micros(p95 as u64)))
                 .style(self.style),
-            Paragraph::new(format!("timeout (>=1s)# {:?}", to)).style(self.style),
+            Paragraph::new(format!(
+                "timeout (>=1s)# {:?} late# {:?} err# {:?}",
+                to,
+                self.late.len(),
+                self.errors.len()
+            ))
+            .style(self.style),
         ]
     }
+
+    fn marker(&self) -> symbols::Marker {
+        if self.simple_graphics {
+            symbols::Marker::Dot
+        } else {
+            symbols::Marker::Braille
+        }
+    }
+}
+
+// Records that something happened now, forgetting anything older than the buffer.
+fn record_event(events: &mut Vec<f64>, buffer: chrono::Duration) {
+    let now = Local::now();
+    events.push(now.timestamp_millis() as f64 / 1_000f64);
+    let earliest_timestamp = (now - buffer).timestamp_millis() as f64 / 1_000f64;
+    events.retain(|timestamp| *timestamp >= earliest_timestamp);
 }
 
 impl<'a> Into<Dataset<'a>> for &'a PlotData {
     fn into(self) -> Dataset<'a> {
         let slice = self.data.as_slice();
         Dataset::default()
-            .marker(if self.simple_graphics {
-                symbols::Marker::Dot
-            } else {
-                symbols::Marker::Braille
-            })
+            .marker(self.marker())
             .style(self.style)
             .graph_type(GraphType::Line)
             .data(slice)
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Result};
+use pinger::dns::{DnsPinger, RecordType};
+use pinger::http::{HttpPinger, Url};
+use pinger::linux::LinuxParser;
+use pinger::replay::ReplayPinger;
+use pinger::tcp::TcpPinger;
+use pinger::udp::UdpPinger;
+use pinger::{ping_with_options, PingHandle, PingOptions, Pinger};
+
+/// Something to measure the latency of, given on the command line as a hostname to ping, a
+/// `tcp://host:port` to connect to, a `udp://host[:port]` running netcheck-reflector, an
+/// `http(s)://` URL to request or a `dns://resolver/name?type=AAAA` to query. Targets can also be
+/// replayed from a recording, see `--replay`.
+pub enum Target {
+    Icmp(String),
+    Tcp { host: String, addr: String },
+    Udp { host: String, addr: String },
+    Http { host: String, url: String },
+    Dns {
+        host: String,
+        resolver: String,
+        name: String,
+        record_type: RecordType,
+    },
+    Replay {
+        name: String,
+        path: String,
+        column: usize,
+        speed: f64,
+    },
+}
+
+impl Target {
+    pub fn parse(target: &str) -> Result<Target> {
+        if let Some(addr) = target.strip_prefix("tcp://") {
+            let (host, port) = addr
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("Missing port in {}", target))?;
+            port.parse::<u16>()
+                .map_err(|_| anyhow!("Invalid port in {}", target))?;
+            return Ok(Target::Tcp {
+                host: host.trim_start_matches('[').trim_end_matches(']').to_string(),
+                addr: addr.to_string(),
+            });
+        }
+        if let Some(addr) = target.strip_prefix("udp://") {
+            // The port defaults to the reflector's
+            return Ok(Target::Udp {
+                host: without_port(addr).to_string(),
+                addr: addr.to_string(),
+            });
+        }
+        if target.starts_with("http://") || target.starts_with("https://") {
+            return Ok(Target::Http {
+                host: Url::parse(target)?.host,
+                url: target.to_string(),
+            });
+        }
+        if let Some(query) = target.strip_prefix("dns://") {
+            let (query, record_type) = match query.split_once("?type=") {
+                Some((query, record_type)) => (query, record_type.parse()?),
+                None => (query, RecordType::A),
+            };
+            let (resolver, name) = query
+                .split_once('/')
+                .filter(|(resolver, name)| !resolver.is_empty() && !name.is_empty())
+                .ok_or_else(|| anyhow!("Expected dns://resolver/name, got {}", target))?;
+            return Ok(Target::Dns {
+                host: without_port(resolver).to_string(),
+                resolver: resolver.to_string(),
+                name: name.to_string(),
+                record_type,
+            });
+        }
+        Ok(Target::Icmp(target.to_string()))
+    }
+
+    /// The hostname or IP address, without any scheme or port.
+    pub fn host(&self) -> &str {
+        match self {
+            Target::Icmp(host) => host,
+            Target::Tcp { host, .. } => host,
+            Target::Udp { host, .. } => host,
+            Target::Http { host, .. } => host,
+            Target::Dns { host, .. } => host,
+            Target::Replay { name, .. } => name,
+        }
+    }
+
+    pub fn start(&self, options: PingOptions) -> Result<PingHandle> {
+        match self {
+            Target::Icmp(host) => ping_with_options(host.clone(), options),
+            Target::Tcp { addr, .. } => {
+                let mut pinger = TcpPinger::default();
+                pinger.set_options(options);
+                pinger.start::<LinuxParser>(addr.clone())
+            }
+            Target::Udp { addr, .. } => {
+                let mut pinger = UdpPinger::default();
+                pinger.set_options(options);
+                pinger.start::<LinuxParser>(addr.clone())
+            }
+            Target::Http { url, .. } => {
+                let mut pinger = HttpPinger::default();
+                pinger.set_options(options);
+                pinger.start::<LinuxParser>(url.clone())
+            }
+            Target::Dns {
+                resolver,
+                name,
+                record_type,
+                ..
+            } => {
+                let mut pinger = DnsPinger::new(name.clone(), *record_type);
+                pinger.set_options(options);
+                pinger.start::<LinuxParser>(resolver.clone())
+            }
+            Target::Replay {
+                path,
+                column,
+                speed,
+                ..
+            } => {
+                let mut pinger = ReplayPinger::default().speed(*speed).column(*column);
+                pinger.set_options(options);
+                pinger.start::<LinuxParser>(path.clone())
+            }
+        }
+    }
+}
+
+// Strips the port from an address that may have one, which IPv6 addresses need brackets for.
+fn without_port(addr: &str) -> &str {
+    if let Some(bracketed) = addr.strip_prefix('[') {
+        bracketed.split(']').next().unwrap_or_default()
+    } else if addr.matches(':').count() == 1 {
+        addr.split(':').next().unwrap_or_default()
+    } else {
+        addr
+    }
+}
@@ -0,0 +1,181 @@
+//! Picks which `ping` implementation (or other way of pinging) to use.
+//!
+//! A backend can be forced with [`crate::PingOptions::backend`] or the [`BACKEND_ENV_VAR`]
+//! environment variable. Otherwise `ping`, or [`crate::PingOptions::program`], is asked for its
+//! version, as the distro name alone doesn't say whether it's iputils or busybox.
+use crate::bsd::BsdVariant;
+use crate::{PingError, PingOptions};
+use anyhow::Result;
+use os_info::Type;
+use std::fmt;
+use std::fmt::Formatter;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Setting this environment variable forces a backend, using the names [`Backend`] is parsed
+/// from, e.g. `native` to use ICMP sockets directly on Linux instead of running the system `ping`.
+pub const BACKEND_ENV_VAR: &str = "NETCHECK_PING_BACKEND";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    /// iputils `ping`, used by most Linux distributions.
+    Iputils,
+    /// The busybox `ping` applet, as used by Alpine.
+    Busybox,
+    /// GNU inetutils `ping`.
+    Inetutils,
+    MacOS,
+    Bsd(BsdVariant),
+    Windows,
+    /// ICMP sockets opened directly, without running `ping`. Linux only.
+    NativeSocket,
+    /// A `ping` program other than the one on the `PATH`. Which implementation it is gets
+    /// probed, assuming iputils if that fails.
+    Custom(String),
+}
+
+impl Backend {
+    /// The backend to use with these options, see the module docs.
+    pub fn detect(options: &PingOptions) -> Result<Backend> {
+        if let Some(backend) = &options.backend {
+            return Ok(backend.clone());
+        }
+        if let Ok(value) = std::env::var(BACKEND_ENV_VAR) {
+            return Ok(value.parse()?);
+        }
+        if cfg!(windows) {
+            return Ok(Backend::Windows);
+        }
+        let os_type = os_info::get().os_type();
+        match os_type {
+            Type::Macos => return Ok(Backend::MacOS),
+            Type::FreeBSD | Type::DragonFly | Type::HardenedBSD | Type::MidnightBSD => {
+                return Ok(Backend::Bsd(BsdVariant::FreeBSD))
+            }
+            Type::OpenBSD => return Ok(Backend::Bsd(BsdVariant::OpenBSD)),
+            Type::NetBSD => return Ok(Backend::Bsd(BsdVariant::NetBSD)),
+            _ => {}
+        }
+        if let Some(backend) = Backend::probe(options.program.as_deref().unwrap_or("ping")) {
+            return Ok(backend);
+        }
+        // Fall back on what the distro usually ships
+        match os_type {
+            Type::Amazon
+            | Type::Arch
+            | Type::CentOS
+            | Type::Debian
+            | Type::EndeavourOS
+            | Type::Fedora
+            | Type::Linux
+            | Type::Manjaro
+            | Type::Mint
+            | Type::openSUSE
+            | Type::OracleLinux
+            | Type::Redhat
+            | Type::RedHatEnterprise
+            | Type::SUSE
+            | Type::Ubuntu
+            | Type::Pop
+            | Type::Solus
+            | Type::Android => Ok(Backend::Iputils),
+            Type::Alpine => Ok(Backend::Busybox),
+            _ => Err(PingError::UnsupportedOS(os_type.to_string()).into()),
+        }
+    }
+
+    /// The shortest interval this backend's `ping` accepts from anyone but root, if it has one.
+    pub fn min_interval(&self) -> Option<Duration> {
+        match self {
+            // Newer iputils releases go down to 2ms, but they can't be told apart from -V
+            Backend::Iputils | Backend::Inetutils => Some(Duration::from_millis(200)),
+            Backend::MacOS => Some(Duration::from_millis(100)),
+            Backend::Bsd(_) => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+
+    /// Works out which implementation a `ping` program is from its version or help output.
+    pub fn probe(program: &str) -> Option<Backend> {
+        for arg in ["-V", "--help"] {
+            let output = Command::new(program)
+                .arg(arg)
+                .env("LANG", "C")
+                .env("LC_ALL", "C")
+                .stdin(Stdio::null())
+                .output()
+                .ok()?;
+            // Busybox complains about -V on stderr, along with its name
+            let text =
+                String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+            if let Some(backend) = Backend::identify(&text) {
+                return Some(backend);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn identify(output: &str) -> Option<Backend> {
+        if output.contains("iputils") {
+            Some(Backend::Iputils)
+        } else if output.contains("BusyBox") {
+            Some(Backend::Busybox)
+        } else if output.contains("inetutils") {
+            Some(Backend::Inetutils)
+        } else {
+            None
+        }
+    }
+}
+
+// Whether we're allowed to ping faster than `Backend::min_interval`
+#[cfg(unix)]
+pub(crate) fn privileged() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn privileged() -> bool {
+    true
+}
+
+impl FromStr for Backend {
+    type Err = PingError;
+
+    fn from_str(s: &str) -> Result<Backend, PingError> {
+        let backend = match s.to_ascii_lowercase().as_str() {
+            "iputils" => Backend::Iputils,
+            "busybox" => Backend::Busybox,
+            "inetutils" => Backend::Inetutils,
+            "macos" => Backend::MacOS,
+            "freebsd" => Backend::Bsd(BsdVariant::FreeBSD),
+            "openbsd" => Backend::Bsd(BsdVariant::OpenBSD),
+            "netbsd" => Backend::Bsd(BsdVariant::NetBSD),
+            "windows" => Backend::Windows,
+            "native" => Backend::NativeSocket,
+            _ => match s.strip_prefix("custom:") {
+                Some(program) if !program.is_empty() => Backend::Custom(program.to_string()),
+                _ => return Err(PingError::UnsupportedBackend(s.to_string())),
+            },
+        };
+        Ok(backend)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Iputils => write!(f, "iputils"),
+            Backend::Busybox => write!(f, "busybox"),
+            Backend::Inetutils => write!(f, "inetutils"),
+            Backend::MacOS => write!(f, "macos"),
+            Backend::Bsd(BsdVariant::FreeBSD) => write!(f, "freebsd"),
+            Backend::Bsd(BsdVariant::OpenBSD) => write!(f, "openbsd"),
+            Backend::Bsd(BsdVariant::NetBSD) => write!(f, "netbsd"),
+            Backend::Windows => write!(f, "windows"),
+            Backend::NativeSocket => write!(f, "native"),
+            Backend::Custom(program) => write!(f, "custom:{}", program),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::summary::SummaryParser;
+use crate::tracker::{SeqTracker, DEFAULT_GRACE};
+use crate::{secs, whole_secs, IpVersion, Parser, PingOptions, PingResult, Pinger};
+use regex::Regex;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // IPv6 replies report the hop limit as hlim= rather than ttl=
+    static ref RE: Regex = Regex::new(
+        r"^(?P<bytes>\d+) bytes from (?P<from>\S+?): icmp_seq=(?P<seq>\d+) (?:ttl|hlim)=(?P<ttl>\d+) time=(?P<time>[0-9.]+) ms"
+    )
+    .unwrap();
+    // e.g. "92 bytes from 10.0.0.1: Destination Host Unreachable", followed by a dump of the
+    // original packet's headers which we ignore.
+    static ref ICMP_ERROR_RE: Regex =
+        Regex::new(r"^\d+ bytes from (?P<from>\S+?): (?P<message>[^=]+)$").unwrap();
+}
+
+/// The BSDs all ship a descendant of the same `ping`, but have picked different flags since.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BsdVariant {
+    #[default]
+    FreeBSD,
+    OpenBSD,
+    NetBSD,
+}
+
+#[derive(Default)]
+pub struct BsdPinger {
+    variant: BsdVariant,
+    options: PingOptions,
+}
+
+impl BsdPinger {
+    pub fn new(variant: BsdVariant) -> BsdPinger {
+        BsdPinger {
+            variant,
+            options: PingOptions::default(),
+        }
+    }
+
+    fn ipv6(&self) -> bool {
+        self.options.ip_version == Some(IpVersion::V6)
+    }
+}
+
+impl Pinger for BsdPinger {
+    // NetBSD still has a separate ping6, the others take -6.
+    fn program(&self) -> &str {
+        if self.variant == BsdVariant::NetBSD && self.ipv6() {
+            "ping6"
+        } else {
+            "ping"
+        }
+    }
+
+    fn ping_args(&self, target: String) -> Vec<String> {
+        let options = &self.options;
+        // Intervals under a second need root on all of them. None of them can pick the outgoing
+        // interface for unicast pings, so that option is ignored.
+        let mut args = vec![format!("-i{}", secs(options.interval))];
+        if let Some(count) = options.count {
+            args.push(format!("-c{}", count));
+        }
+        if let Some(size) = options.size {
+            args.push(format!("-s{}", size));
+        }
+        match self.variant {
+            BsdVariant::FreeBSD => {
+                if let Some(ttl) = options.ttl {
+                    let flag = if self.ipv6() { "-h" } else { "-m" };
+                    args.push(format!("{}{}", flag, ttl));
+                }
+                // The per-probe wait is in milliseconds
+                if let Some(timeout) = options.timeout {
+                    args.push(format!("-W{}", timeout.as_millis()));
+                }
+                if let Some(deadline) = options.deadline {
+                    args.push(format!("-t{}", whole_secs(deadline)));
+                }
+                if options.dont_fragment && !self.ipv6() {
+                    args.push("-D".to_string());
+                }
+                if let Some(source) = options.source {
+                    args.push(format!("-S{}", source));
+                }
+            }
+            // OpenBSD has no overall deadline
+            BsdVariant::OpenBSD => {
+                if let Some(ttl) = options.ttl {
+                    args.push(format!("-t{}", ttl));
+                }
+                if let Some(timeout) = options.timeout {
+                    args.push(format!("-w{}", whole_secs(timeout)));
+                }
+                if options.dont_fragment && !self.ipv6() {
+                    args.push("-D".to_string());
+                }
+                if let Some(source) = options.source {
+                    args.push(format!("-I{}", source));
+                }
+            }
+            // NetBSD has no per-probe wait
+            BsdVariant::NetBSD => {
+                if let Some(ttl) = options.ttl {
+                    let flag = if self.ipv6() { "-h" } else { "-T" };
+                    args.push(format!("{}{}", flag, ttl));
+                }
+                if let Some(deadline) = options.deadline {
+                    args.push(format!("-w{}", whole_secs(deadline)));
+                }
+                if options.dont_fragment && !self.ipv6() {
+                    args.push("-D".to_string());
+                }
+                if let Some(source) = options.source {
+                    let flag = if self.ipv6() { "-S" } else { "-I" };
+                    args.push(format!("{}{}", flag, source));
+                }
+            }
+        }
+        // NetBSD picks the address family with the program instead
+        if self.variant != BsdVariant::NetBSD {
+            match options.ip_version {
+                Some(IpVersion::V4) => args.push("-4".to_string()),
+                Some(IpVersion::V6) => args.push("-6".to_string()),
+                None => {}
+            }
+        }
+        args.push(target);
+        args
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+
+    // None of them report a lost reply until the next one arrives, if at all.
+    fn seq_tracker(&self) -> Option<SeqTracker> {
+        let grace = self.options.timeout.unwrap_or_default().max(DEFAULT_GRACE);
+        let tracker = SeqTracker::new(self.options.interval, grace, Instant::now());
+        Some(match self.options.count {
+            Some(count) => tracker.count(count),
+            None => tracker,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct BsdParser {
+    summary: SummaryParser,
+}
+
+impl Parser for BsdParser {
+    fn parse(&mut self, line: String) -> Option<PingResult> {
+        if let Some(summary) = self.summary.parse(&line) {
+            return summary;
+        }
+        if line.starts_with("PING ") {
+            return None;
+        }
+        if line.starts_with("Request timeout") {
+            return Some(PingResult::Timeout(line));
+        }
+        if ICMP_ERROR_RE.is_match(&line) {
+            return self.extract_icmp_error(&ICMP_ERROR_RE, line);
+        }
+        self.extract_regex(&RE, line)
+    }
+
+    fn finish(&mut self) -> Option<PingResult> {
+        self.summary.finish()
+    }
+}
@@ -0,0 +1,344 @@
+//! Times DNS queries sent straight to a resolver, as a failing resolver looks just like the
+//! internet being down.
+//!
+//! The target is the resolver, as an address or `host:port` (port 53 if left out), and the name
+//! and record type to query are given to [`DnsPinger::new`]. Queries go over UDP, and are retried
+//! over TCP if the answer was truncated. A response with an RCODE other than `NOERROR` is
+//! reported as [`PingResult::DnsError`].
+use crate::{
+    pick_addr, probe_every_interval, Parser, PingError, PingHandle, PingOptions, PingReply,
+    PingResult, Pinger,
+};
+use anyhow::Result;
+use std::fmt;
+use std::fmt::Formatter;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_PORT: u16 = 53;
+// Resolvers that have to recurse can be slow, but anything over a couple of seconds is a failure
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+// Plenty for a UDP answer without EDNS, which is limited to 512 bytes
+const UDP_BUFFER: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordType {
+    A,
+    Ns,
+    Cname,
+    Soa,
+    Ptr,
+    Mx,
+    Txt,
+    Aaaa,
+    Srv,
+    Any,
+}
+
+impl RecordType {
+    fn code(self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+            RecordType::Any => 255,
+        }
+    }
+}
+
+impl FromStr for RecordType {
+    type Err = PingError;
+
+    fn from_str(s: &str) -> Result<RecordType, PingError> {
+        let record_type = match s.to_ascii_uppercase().as_str() {
+            "A" => RecordType::A,
+            "NS" => RecordType::Ns,
+            "CNAME" => RecordType::Cname,
+            "SOA" => RecordType::Soa,
+            "PTR" => RecordType::Ptr,
+            "MX" => RecordType::Mx,
+            "TXT" => RecordType::Txt,
+            "AAAA" => RecordType::Aaaa,
+            "SRV" => RecordType::Srv,
+            "ANY" => RecordType::Any,
+            _ => return Err(PingError::InvalidQuery(s.to_string())),
+        };
+        Ok(record_type)
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RecordType::A => "A",
+            RecordType::Ns => "NS",
+            RecordType::Cname => "CNAME",
+            RecordType::Soa => "SOA",
+            RecordType::Ptr => "PTR",
+            RecordType::Mx => "MX",
+            RecordType::Txt => "TXT",
+            RecordType::Aaaa => "AAAA",
+            RecordType::Srv => "SRV",
+            RecordType::Any => "ANY",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The name of a response code, e.g. `NXDOMAIN` for 3.
+pub fn rcode_name(rcode: u8) -> String {
+    let name = match rcode {
+        0 => "NOERROR",
+        1 => "FORMERR",
+        2 => "SERVFAIL",
+        3 => "NXDOMAIN",
+        4 => "NOTIMP",
+        5 => "REFUSED",
+        _ => return format!("RCODE{}", rcode),
+    };
+    name.to_string()
+}
+
+pub struct DnsPinger {
+    options: PingOptions,
+    name: String,
+    record_type: RecordType,
+}
+
+impl Default for DnsPinger {
+    fn default() -> Self {
+        DnsPinger::new(".", RecordType::Ns)
+    }
+}
+
+// The header of a response, which is all we look at
+struct Answer {
+    rcode: u8,
+    answers: u16,
+    bytes: usize,
+}
+
+impl DnsPinger {
+    /// Queries for `record_type` records of `name`. The default pinger asks for the root's name
+    /// servers.
+    pub fn new(name: impl Into<String>, record_type: RecordType) -> DnsPinger {
+        DnsPinger {
+            options: PingOptions::default(),
+            name: name.into(),
+            record_type,
+        }
+    }
+
+    fn resolve(&self, target: &str) -> Result<SocketAddr> {
+        let addrs = if let Ok(ip) = target.parse::<IpAddr>() {
+            vec![SocketAddr::new(ip, DEFAULT_PORT)]
+        } else if let Ok(addrs) = target.to_socket_addrs() {
+            addrs.collect()
+        } else {
+            (target, DEFAULT_PORT)
+                .to_socket_addrs()
+                .map(Iterator::collect)
+                .unwrap_or_default()
+        };
+        pick_addr(addrs, self.options.ip_version)
+            .ok_or_else(|| PingError::HostnameError(target.to_string()).into())
+    }
+
+    fn query(&self, id: u16) -> Result<Vec<u8>, PingError> {
+        let invalid = || PingError::InvalidQuery(self.name.clone());
+        let mut query = Vec::with_capacity(18 + self.name.len());
+        query.extend_from_slice(&id.to_be_bytes());
+        // A standard query with recursion desired, and a single question
+        query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let name = self.name.trim_end_matches('.');
+        if name.len() > 253 {
+            return Err(invalid());
+        }
+        for label in name.split('.').filter(|_| !name.is_empty()) {
+            if label.is_empty() || label.len() > 63 {
+                return Err(invalid());
+            }
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&self.record_type.code().to_be_bytes());
+        // The IN class
+        query.extend_from_slice(&[0, 1]);
+        Ok(query)
+    }
+
+    fn exchange_udp(
+        &self,
+        socket: &UdpSocket,
+        resolver: SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<(Vec<u8>, bool)> {
+        socket.send_to(query, resolver)?;
+        let sent = Instant::now();
+        let mut buffer = [0; UDP_BUFFER];
+        loop {
+            let remaining = timeout
+                .checked_sub(sent.elapsed())
+                .filter(|remaining| !remaining.is_zero())
+                .ok_or_else(|| io::Error::from(ErrorKind::TimedOut))?;
+            socket.set_read_timeout(Some(remaining))?;
+            let (size, from) = socket.recv_from(&mut buffer)?;
+            let response = &buffer[..size];
+            // Skip anything that isn't the answer to this query, like a late one to the last
+            if from != resolver || size < 12 || response[..2] != query[..2] {
+                continue;
+            }
+            let truncated = response[2] & 0x02 != 0;
+            return Ok((response.to_vec(), truncated));
+        }
+    }
+
+    fn exchange_tcp(
+        &self,
+        resolver: SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let mut stream = TcpStream::connect_timeout(&resolver, timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        // Messages over TCP are prefixed with their length
+        let mut message = (query.len() as u16).to_be_bytes().to_vec();
+        message.extend_from_slice(query);
+        stream.write_all(&message)?;
+        let mut length = [0; 2];
+        stream.read_exact(&mut length)?;
+        let mut response = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut response)?;
+        if response.len() < 12 || response[..2] != query[..2] {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "response doesn't match the query",
+            ));
+        }
+        Ok(response)
+    }
+
+    // Returns the header of the response, and whether it took TCP to get it
+    fn lookup(
+        &self,
+        socket: &UdpSocket,
+        resolver: SocketAddr,
+        query: &[u8],
+        timeout: Duration,
+    ) -> io::Result<(Answer, bool)> {
+        let started = Instant::now();
+        let (mut response, truncated) = self.exchange_udp(socket, resolver, query, timeout)?;
+        if truncated {
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            response = self.exchange_tcp(resolver, query, remaining)?;
+        }
+        if response[2] & 0x80 == 0 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a response"));
+        }
+        let answer = Answer {
+            rcode: response[3] & 0x0f,
+            answers: u16::from_be_bytes([response[6], response[7]]),
+            bytes: response.len(),
+        };
+        Ok((answer, truncated))
+    }
+}
+
+impl Pinger for DnsPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let options = self.options.clone();
+        let resolver = self.resolve(&target)?;
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let local = match (options.source, resolver) {
+            (Some(source), _) => SocketAddr::new(source, 0),
+            (None, SocketAddr::V4(_)) => ([0, 0, 0, 0], 0).into(),
+            (None, SocketAddr::V6(_)) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        // Fail now rather than on every query
+        self.query(0)?;
+        let pinger = DnsPinger {
+            options,
+            name: self.name.clone(),
+            record_type: self.record_type,
+        };
+        // Query IDs shouldn't be predictable, or be the same as a previous run's
+        let base = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|now| now.subsec_nanos() as u16)
+            .unwrap_or_default();
+
+        let question = format!("{} {}", pinger.name, pinger.record_type);
+        Ok(probe_every_interval(pinger.options.clone(), move |seq| {
+            let query = pinger
+                .query(base.wrapping_add(seq as u16))
+                .expect("query was checked before starting");
+            let sent = Instant::now();
+            let result = pinger.lookup(&socket, resolver, &query, timeout);
+            let duration = sent.elapsed();
+            match result {
+                Ok((answer, tcp)) => {
+                    let reply = PingReply {
+                        seq: Some(seq),
+                        bytes: Some(answer.bytes),
+                        from: Some(resolver.ip()),
+                        ..PingReply::new(format!(
+                            "{} for {} from {}{} seq={} answers={} time={:.3} ms",
+                            rcode_name(answer.rcode),
+                            question,
+                            resolver,
+                            if tcp { " over tcp" } else { "" },
+                            seq,
+                            answer.answers,
+                            duration.as_micros() as f64 / 1_000f64
+                        ))
+                    };
+                    if answer.rcode == 0 {
+                        PingResult::Pong(duration, reply)
+                    } else {
+                        PingResult::DnsError(answer.rcode, reply)
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    PingResult::Timeout(format!(
+                        "no answer yet for {} from {} seq={}",
+                        question, resolver, seq
+                    ))
+                }
+                Err(e) => PingResult::Unreachable(PingReply {
+                    seq: Some(seq),
+                    ..PingReply::new(format!(
+                        "{}, querying {} for {} seq={}",
+                        e, resolver, question, seq
+                    ))
+                }),
+            }
+        }))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
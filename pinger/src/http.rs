@@ -0,0 +1,396 @@
+//! Times HTTP(S) requests, for when it's a web service rather than a host that's slow.
+//!
+//! Each probe is a `GET` on a fresh connection, so that the reply's [`Phases`] show whether the
+//! time went on resolving the name, connecting, the TLS handshake or waiting for the server.
+//! A status outside 2xx is reported as [`PingResult::HttpError`]. `https://` URLs need the `tls`
+//! feature.
+use crate::{
+    pick_addr, probe_every_interval, Parser, PingError, PingHandle, PingOptions, PingReply,
+    PingResult, Pinger,
+};
+use anyhow::Result;
+#[cfg(feature = "tls")]
+use std::convert::TryFrom;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Servers can take a while to answer, so this is more generous than for a TCP handshake
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long each part of a request took. These don't overlap, and whatever is left of the total
+/// was spent reading the rest of the response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Phases {
+    pub dns: Duration,
+    pub connect: Duration,
+    /// The TLS handshake, for `https://` URLs.
+    pub tls: Option<Duration>,
+    /// From sending the request to the first byte of the response.
+    pub first_byte: Duration,
+}
+
+/// The parts of an `http://` or `https://` URL needed to make a request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// The path and query, which is `/` if the URL doesn't have one.
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, PingError> {
+        let invalid = || PingError::InvalidUrl(url.to_string());
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid());
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(idx) if rest[idx..].starts_with('?') => {
+                (&rest[..idx], format!("/{}", &rest[idx..]))
+            }
+            Some(idx) => (&rest[..idx], rest[idx..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let default_port = if tls { 443 } else { 80 };
+        // IPv6 addresses are in brackets, as they contain colons themselves
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, port) = bracketed.split_once(']').ok_or_else(invalid)?;
+            // Only a port can come between the address and the path
+            match port {
+                "" => (host, None),
+                port => (host, Some(port.strip_prefix(':').ok_or_else(invalid)?)),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid())?,
+            None => default_port,
+        };
+        Ok(Url {
+            tls,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    // The Host header, which leaves out the port if it's the default
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => host,
+            (_, port) => format!("{}:{}", host, port),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HttpPinger {
+    options: PingOptions,
+}
+
+// A failed request, with what was being done when it failed
+struct Failure {
+    doing: &'static str,
+    error: io::Error,
+    addr: Option<SocketAddr>,
+}
+
+fn failed(doing: &'static str, addr: Option<SocketAddr>) -> impl FnOnce(io::Error) -> Failure {
+    move |error| Failure { doing, error, addr }
+}
+
+// What came back from a request that got a response
+struct Response {
+    status: u16,
+    status_line: String,
+    bytes: usize,
+    addr: SocketAddr,
+    phases: Phases,
+}
+
+impl HttpPinger {
+    fn request(&self, url: &Url, tls: &Option<Tls>) -> Result<Response, Failure> {
+        let timeout = self.options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+        let started = Instant::now();
+        let addrs = (url.host.as_str(), url.port)
+            .to_socket_addrs()
+            .map_err(failed("resolving", None))?;
+        let addr = pick_addr(addrs, self.options.ip_version).ok_or_else(|| Failure {
+            doing: "resolving",
+            error: io::Error::new(ErrorKind::NotFound, "no addresses of the right IP version"),
+            addr: None,
+        })?;
+        let dns = started.elapsed();
+
+        let connecting = Instant::now();
+        let stream = TcpStream::connect_timeout(&addr, timeout)
+            .map_err(failed("connecting to", Some(addr)))?;
+        let connect = connecting.elapsed();
+        stream
+            .set_read_timeout(Some(timeout))
+            .and_then(|_| stream.set_write_timeout(Some(timeout)))
+            .map_err(failed("connecting to", Some(addr)))?;
+
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: pinger/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+            url.path,
+            url.host_header(),
+            env!("CARGO_PKG_VERSION")
+        );
+        let (tls, exchanged) = match tls {
+            #[cfg(feature = "tls")]
+            Some(tls) => {
+                let handshaking = Instant::now();
+                let stream = tls
+                    .handshake(&url.host, stream)
+                    .map_err(failed("handshaking with", Some(addr)))?;
+                (Some(handshaking.elapsed()), exchange(stream, &request))
+            }
+            #[cfg(not(feature = "tls"))]
+            Some(tls) => match *tls {},
+            None => (None, exchange(stream, &request)),
+        };
+        let exchanged = exchanged.map_err(failed("requesting from", Some(addr)))?;
+        Ok(Response {
+            status: exchanged.status,
+            status_line: exchanged.status_line,
+            bytes: exchanged.bytes,
+            addr,
+            phases: Phases {
+                dns,
+                connect,
+                tls,
+                first_byte: exchanged.first_byte,
+            },
+        })
+    }
+}
+
+struct Exchanged {
+    status: u16,
+    status_line: String,
+    bytes: usize,
+    first_byte: Duration,
+}
+
+// Sends the request and reads the whole response, as the total time includes downloading it.
+fn exchange<S: Read + Write>(mut stream: S, request: &str) -> io::Result<Exchanged> {
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    let sent = Instant::now();
+
+    let mut buffer = [0; 8192];
+    let mut head = Vec::new();
+    let mut first_byte = None;
+    let mut bytes = 0;
+    let mut remaining: Option<usize> = None;
+    loop {
+        let read = match stream.read(&mut buffer) {
+            Ok(read) => read,
+            // Servers often close TLS connections without saying so, which is the end either way
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && first_byte.is_some() => 0,
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+        first_byte.get_or_insert_with(|| sent.elapsed());
+        bytes += read;
+        match remaining.as_mut() {
+            Some(remaining) => *remaining = remaining.saturating_sub(read),
+            None => {
+                head.extend_from_slice(&buffer[..read]);
+                if let Some(end) = find(&head, b"\r\n\r\n") {
+                    // Without a length the server closes the connection once it's done
+                    let length = content_length(&head[..end]).unwrap_or(usize::MAX);
+                    remaining = Some(length.saturating_sub(head.len() - end - 4));
+                }
+            }
+        }
+        if remaining == Some(0) {
+            break;
+        }
+    }
+
+    let first_byte = first_byte.ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::UnexpectedEof,
+            "connection closed without a response",
+        )
+    })?;
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default().to_string();
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .filter(|_| status_line.starts_with("HTTP/"))
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not an HTTP response"))?;
+    Ok(Exchanged {
+        status,
+        status_line,
+        bytes,
+        first_byte,
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn content_length(head: &[u8]) -> Option<usize> {
+    String::from_utf8_lossy(head).lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+impl Pinger for HttpPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let url = Url::parse(&target)?;
+        let tls = match url.tls {
+            true => Some(Tls::new().ok_or_else(|| {
+                PingError::InvalidUrl(format!("{} (built without the tls feature)", target))
+            })?),
+            false => None,
+        };
+        let pinger = HttpPinger {
+            options: self.options.clone(),
+        };
+
+        Ok(probe_every_interval(pinger.options.clone(), move |seq| {
+            let sent = Instant::now();
+            let result = pinger.request(&url, &tls);
+            let duration = sent.elapsed();
+            match result {
+                Ok(response) => {
+                    let reply = PingReply {
+                        seq: Some(seq),
+                        bytes: Some(response.bytes),
+                        from: Some(response.addr.ip()),
+                        phases: Some(response.phases),
+                        ..PingReply::new(format!(
+                            "{} from {} seq={} time={:.3} ms",
+                            response.status_line,
+                            response.addr,
+                            seq,
+                            duration.as_micros() as f64 / 1_000f64
+                        ))
+                    };
+                    if (200..300).contains(&response.status) {
+                        PingResult::Pong(duration, reply)
+                    } else {
+                        PingResult::HttpError(response.status, reply)
+                    }
+                }
+                Err(failure)
+                    if matches!(
+                        failure.error.kind(),
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock
+                    ) =>
+                {
+                    PingResult::Timeout(format!("no answer yet for {} seq={}", target, seq))
+                }
+                Err(failure) => PingResult::Unreachable(PingReply {
+                    seq: Some(seq),
+                    from: failure.addr.map(|addr| addr.ip()),
+                    ..PingReply::new(format!(
+                        "{}, {} {} seq={}",
+                        failure.error, failure.doing, target, seq
+                    ))
+                }),
+            }
+        }))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
+
+#[cfg(feature = "tls")]
+struct Tls {
+    config: Arc<rustls::ClientConfig>,
+}
+
+#[cfg(feature = "tls")]
+impl Tls {
+    fn new() -> Option<Tls> {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Some(Tls {
+            config: Arc::new(config),
+        })
+    }
+
+    fn handshake(
+        &self,
+        host: &str,
+        mut stream: TcpStream,
+    ) -> io::Result<rustls::StreamOwned<rustls::ClientConnection, TcpStream>> {
+        let name = rustls::ServerName::try_from(host)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+        let mut connection = rustls::ClientConnection::new(Arc::clone(&self.config), name)
+            .map_err(io::Error::other)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut stream)?;
+        }
+        Ok(rustls::StreamOwned::new(connection, stream))
+    }
+}
+
+// Without TLS support there's no way to make one of these
+#[cfg(not(feature = "tls"))]
+enum Tls {}
+
+#[cfg(not(feature = "tls"))]
+impl Tls {
+    fn new() -> Option<Tls> {
+        None
+    }
+}
@@ -0,0 +1,242 @@
+//! Minimal ICMP echo packet handling shared by the socket based backends, and the ICMP errors
+//! the kernel queues on sockets for tracing.
+use socket2::Socket;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::AsRawFd;
+
+const ECHO_REQUEST_V4: u8 = 8;
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REQUEST_V6: u8 = 128;
+const ECHO_REPLY_V6: u8 = 129;
+
+/// Size of the payload carried by our echo requests, matching the `ping` default.
+pub const PAYLOAD_SIZE: usize = 56;
+
+#[derive(Debug, PartialEq)]
+pub struct EchoReply {
+    pub ident: u16,
+    pub seq: u16,
+    /// Size of the ICMP message (header and payload), as reported by `ping`.
+    pub size: usize,
+    pub ttl: Option<u8>,
+}
+
+/// Builds an ICMP (or ICMPv6) echo request. The checksum is always filled in; for ICMPv6 the
+/// kernel overwrites it as it covers the pseudo header.
+pub fn echo_request(target: IpAddr, ident: u16, seq: u16, payload_size: usize) -> Vec<u8> {
+    let mut packet = vec![0u8; 8 + payload_size];
+    packet[0] = match target {
+        IpAddr::V4(_) => ECHO_REQUEST_V4,
+        IpAddr::V6(_) => ECHO_REQUEST_V6,
+    };
+    packet[4..6].copy_from_slice(&ident.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, byte) in packet[8..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let sum = checksum(&packet);
+    packet[2..4].copy_from_slice(&sum.to_be_bytes());
+    packet
+}
+
+/// Parses an echo reply. Raw IPv4 sockets hand us the IP header as well, which has to be
+/// skipped with `has_ip_header`; anything that isn't an echo reply yields `None`.
+pub fn parse_echo_reply(packet: &[u8], target: IpAddr, has_ip_header: bool) -> Option<EchoReply> {
+    let (icmp, ttl) = if has_ip_header {
+        let header_len = ((*packet.first()? & 0x0f) as usize) * 4;
+        (packet.get(header_len..)?, packet.get(8).copied())
+    } else {
+        (packet, None)
+    };
+    if icmp.len() < 8 {
+        return None;
+    }
+    let expected = match target {
+        IpAddr::V4(_) => ECHO_REPLY_V4,
+        IpAddr::V6(_) => ECHO_REPLY_V6,
+    };
+    if icmp[0] != expected || icmp[1] != 0 {
+        return None;
+    }
+    Some(EchoReply {
+        ident: u16::from_be_bytes([icmp[4], icmp[5]]),
+        seq: u16::from_be_bytes([icmp[6], icmp[7]]),
+        size: icmp.len(),
+        ttl,
+    })
+}
+
+/// Asks the kernel to attach the TTL (or IPv6 hop limit) of each packet we receive, for
+/// sockets that don't give us the IP header.
+pub fn enable_ttl(socket: &Socket, target: IpAddr) -> io::Result<()> {
+    match target {
+        IpAddr::V4(_) => set_option(socket, libc::IPPROTO_IP, libc::IP_RECVTTL, 1),
+        IpAddr::V6(_) => set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1),
+    }
+}
+
+/// An ICMP error that a router (or the destination) sent back about a packet from a socket with
+/// [`enable_errors`] set.
+#[derive(Debug, PartialEq)]
+pub struct IcmpError {
+    pub from: IpAddr,
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+/// Asks the kernel to queue ICMP errors about the packets we send on the socket, rather than
+/// only failing the next call with a bare errno. See `IP_RECVERR` in `ip(7)`.
+pub fn enable_errors(socket: &Socket, target: IpAddr) -> io::Result<()> {
+    match target {
+        IpAddr::V4(_) => set_option(socket, libc::IPPROTO_IP, libc::IP_RECVERR, 1),
+        IpAddr::V6(_) => set_option(socket, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1),
+    }
+}
+
+/// Takes the next error queued on a socket with [`enable_errors`] set. Errors that didn't come
+/// from an ICMP message, such as a local `EMSGSIZE`, are returned as `Ok(None)`.
+pub fn recv_error(socket: &Socket) -> io::Result<Option<IcmpError>> {
+    let mut buffer = [0u8; 1500];
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    // u64 to keep the control messages aligned
+    let mut control = [0u64; 16];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let (level, kind) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+        if (level, kind) == (libc::IPPROTO_IP, libc::IP_RECVERR)
+            || (level, kind) == (libc::IPPROTO_IPV6, libc::IPV6_RECVERR)
+        {
+            let err = unsafe { libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err };
+            let (origin, icmp_type, code) =
+                unsafe { ((*err).ee_origin, (*err).ee_type, (*err).ee_code) };
+            if origin != libc::SO_EE_ORIGIN_ICMP && origin != libc::SO_EE_ORIGIN_ICMP6 {
+                return Ok(None);
+            }
+            let from = unsafe { sockaddr_ip(libc::SO_EE_OFFENDER(err)) };
+            return Ok(from.map(|from| IcmpError {
+                from,
+                icmp_type,
+                code,
+            }));
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok(None)
+}
+
+// Reads the address out of a sockaddr the kernel gave us, which may not be aligned.
+unsafe fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    match std::ptr::read_unaligned(addr).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = std::ptr::read_unaligned(addr as *const libc::sockaddr_in);
+            Some(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let addr = std::ptr::read_unaligned(addr as *const libc::sockaddr_in6);
+            Some(Ipv6Addr::from(addr.sin6_addr.s6_addr).into())
+        }
+        _ => None,
+    }
+}
+
+/// Sets the don't fragment bit on outgoing packets by turning on path MTU discovery.
+pub fn set_dont_fragment(socket: &Socket, target: IpAddr) -> io::Result<()> {
+    match target {
+        IpAddr::V4(_) => set_option(
+            socket,
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        ),
+        IpAddr::V6(_) => set_option(
+            socket,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        ),
+    }
+}
+
+fn set_option(
+    socket: &Socket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receives a packet, returning its size and the TTL requested with [`enable_ttl`].
+pub fn recv(socket: &Socket, buffer: &mut [u8]) -> io::Result<(usize, Option<u8>)> {
+    let mut iov = libc::iovec {
+        iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buffer.len(),
+    };
+    // u64 to keep the control messages aligned
+    let mut control = [0u64; 8];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let size = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if size < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut ttl = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let (level, kind) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+        if (level, kind) == (libc::IPPROTO_IP, libc::IP_TTL)
+            || (level, kind) == (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT)
+        {
+            let value =
+                unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::c_int) };
+            ttl = Some(value as u8);
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+    Ok((size as usize, ttl))
+}
+
+/// The internet checksum from RFC 1071.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
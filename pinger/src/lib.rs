@@ -7,62 +7,143 @@
 /// let stream = ping("tomforb.es".to_string()).expect("Error pinging");
 /// for message in stream {
 ///     match message {
-///         PingResult::Pong(duration, line) => println!("{:?} (line: {})", duration, line),
+///         PingResult::Pong(duration, reply) => println!("{:?} (line: {})", duration, reply.line),
 ///         PingResult::Timeout(_) => println!("Timeout!"),
 ///         PingResult::Unknown(line) => println!("Unknown line: {}", line),
+///         PingResult::Unreachable(reply)
+///         | PingResult::TtlExceeded(reply)
+///         | PingResult::Redirect(reply) => println!("ICMP error: {}", reply.line),
+///         PingResult::HttpError(status, _) => println!("HTTP status {}", status),
+///         PingResult::DnsError(rcode, _) => println!("DNS RCODE {}", rcode),
+///         PingResult::Summary(summary) => println!("Done: {}", summary),
+///         PingResult::Warning(warning) => println!("Warning: {}", warning),
+///         PingResult::Error(..) => println!("Error: {}", message),
 ///     }
 /// }
 /// ```
 use anyhow::Result;
-use os_info::Type;
 use regex::Regex;
+use stats::{PingStats, StatsCollector};
+use std::collections::VecDeque;
 use std::fmt::Formatter;
+use std::io;
 use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
-use std::sync::mpsc;
-use std::time::Duration;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use std::{fmt, thread};
 use thiserror::Error;
+use tracker::{Outstanding, SeqTracker};
 
 #[macro_use]
 extern crate lazy_static;
 
+pub mod backend;
+pub mod bsd;
+pub mod dns;
+pub mod http;
+#[cfg(target_os = "linux")]
+mod icmp;
 pub mod linux;
 // pub mod alpine'
 pub mod macos;
+pub mod replay;
+pub mod route;
+pub mod stats;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod summary;
+pub mod tcp;
+#[cfg(target_os = "linux")]
+pub mod trace;
+pub mod traceroute;
+pub mod tracker;
+pub mod udp;
 #[cfg(windows)]
 pub mod windows;
 
+pub use backend::{Backend, BACKEND_ENV_VAR};
+#[cfg(feature = "async")]
+pub use stream::ping_stream;
+pub use summary::PingSummary;
+
 #[cfg(test)]
 mod test;
 
 pub trait Pinger: Default {
-    fn start<P>(&self, target: String) -> Result<mpsc::Receiver<PingResult>>
+    fn start<P>(&self, target: String) -> Result<PingHandle>
     where
         P: Parser,
     {
         let (tx, rx) = mpsc::channel();
-        let args = self.ping_args(target);
+        let program = self.program().to_string();
+        let mut child = Command::new(&program)
+            .args(self.ping_args(target))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Required to ensure that the output is formatted in the way we expect, not
+            // using locale specific delimiters.
+            .env("LANG", "C")
+            .env("LC_ALL", "C")
+            .spawn()
+            .map_err(|e| PingError::SpawnFailed(program, e))?;
+        let stdout = child.stdout.take().expect("child did not have a stdout");
+        let stderr = child.stderr.take().expect("child did not have a stderr");
+        let child = Arc::new(Mutex::new(child));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = PingHandle::with_child(rx, Arc::clone(&child), Arc::clone(&stop));
+        let tracker = self.seq_tracker().unwrap_or_else(SeqTracker::reconciling);
+        let synthesise = tracker.synthesises_timeouts();
+        let tracker = Arc::new(Mutex::new(tracker));
+
+        // Without timeouts from ping itself, check regularly for replies that are overdue.
+        if synthesise {
+            let tracker = Arc::clone(&tracker);
+            let tx = tx.clone();
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || loop {
+                thread::sleep(TRACKER_TICK);
+                let mut tracker = tracker.lock().unwrap();
+                if stop.load(Ordering::Acquire) || tracker.is_finished() {
+                    break;
+                }
+                for result in tracker.tick(Instant::now()) {
+                    if tx.send(result).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+
+        // Read stderr separately so that a chatty ping can't block on a full pipe. Only the last
+        // few lines are kept, as they're all we need to explain why ping exited.
+        let stderr_reader = thread::spawn(move || {
+            let mut lines = VecDeque::new();
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                if lines.len() == 10 {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            Vec::from(lines).join("\n")
+        });
 
         thread::spawn(move || {
-            let mut child = Command::new("ping")
-                .args(args)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                // Required to ensure that the output is formatted in the way we expect, not
-                // using locale specific delimiters.
-                .env("LANG", "C")
-                .env("LC_ALL", "C")
-                .spawn()
-                .expect("Failed to run ping");
-            let parser = P::default();
-            let stdout = child.stdout.take().expect("child did not have a stdout");
+            let mut parser = P::default();
             let reader = BufReader::new(stdout).lines();
             for line in reader {
                 match line {
                     Ok(msg) => {
                         if let Some(result) = parser.parse(msg) {
-                            if tx.send(result).is_err() {
+                            // Sent while locked, so that results stay in order
+                            let mut tracker = tracker.lock().unwrap();
+                            let sent = tracker
+                                .track(result, Instant::now())
+                                .into_iter()
+                                .all(|result| tx.send(result).is_ok());
+                            if !sent {
                                 break;
                             }
                         }
@@ -70,18 +151,47 @@ pub trait Pinger: Default {
                     Err(_) => break,
                 }
             }
+            tracker.lock().unwrap().finish();
+            if let Some(result) = parser.finish() {
+                let _ = tx.send(result);
+            }
+            let stderr = stderr_reader.join().unwrap_or_default();
+            let status = child.lock().unwrap().wait().ok();
+            // Being stopped by the handle isn't a failure
+            if stop.load(Ordering::Acquire) || status.is_some_and(|s| s.success()) {
+                return;
+            }
+            let _ = tx.send(PingResult::Error(stderr, status));
         });
 
-        Ok(rx)
+        Ok(handle)
     }
 
     fn set_interval(&mut self, interval: Duration);
 
+    /// Configures the pinger. Pingers that don't override this only honour the interval.
+    fn set_options(&mut self, options: PingOptions) {
+        self.set_interval(options.interval);
+    }
+
+    fn program(&self) -> &str {
+        "ping"
+    }
+
     fn ping_args(&self, target: String) -> Vec<String> {
         return vec![target];
     }
+
+    /// Pingers whose `ping` doesn't report timeouts itself return a tracker that synthesises them.
+    /// Otherwise replies are only reconciled with earlier timeouts.
+    fn seq_tracker(&self) -> Option<SeqTracker> {
+        None
+    }
 }
 
+// How often overdue replies are checked for
+pub(crate) const TRACKER_TICK: Duration = Duration::from_millis(100);
+
 // Default empty implementation of a pinger.
 #[derive(Default)]
 pub struct SimplePinger {}
@@ -91,7 +201,12 @@ impl Pinger for SimplePinger {
 }
 
 pub trait Parser: Default {
-    fn parse(&self, line: String) -> Option<PingResult>;
+    fn parse(&mut self, line: String) -> Option<PingResult>;
+
+    /// Called once there's no more output, to pass on anything the parser held back.
+    fn finish(&mut self) -> Option<PingResult> {
+        None
+    }
 
     fn extract_regex(&self, regex: &Regex, line: String) -> Option<PingResult> {
         let cap = regex.captures(&line)?;
@@ -102,15 +217,109 @@ pub trait Parser: Default {
             .parse::<f32>()
             .expect("time cannot be parsed as f32");
         let duration = Duration::from_micros((time * 1000f32) as u64);
-        Some(PingResult::Pong(duration, line))
+        let reply = PingReply {
+            seq: cap.name("seq").and_then(|m| m.as_str().parse().ok()),
+            ttl: cap.name("ttl").and_then(|m| m.as_str().parse().ok()),
+            bytes: cap.name("bytes").and_then(|m| m.as_str().parse().ok()),
+            from: cap.name("from").and_then(|m| m.as_str().parse().ok()),
+            duplicate: line.contains("(DUP!)"),
+            ..PingReply::new(line.clone())
+        };
+        Some(PingResult::Pong(duration, reply))
+    }
+
+    /// Extracts an ICMP error reported by `ping`. The regex needs a group named `message` holding
+    /// the error text, and may capture the `from` address and `seq` it was reported for.
+    fn extract_icmp_error(&self, regex: &Regex, line: String) -> Option<PingResult> {
+        let cap = regex.captures(&line)?;
+        let message = cap.name("message")?.as_str().to_ascii_lowercase();
+        let reply = PingReply {
+            seq: cap.name("seq").and_then(|m| m.as_str().parse().ok()),
+            from: cap.name("from").and_then(|m| m.as_str().parse().ok()),
+            ..PingReply::new(line.clone())
+        };
+        if message.contains("unreachable")
+            || message.contains("prohibited")
+            || message.contains("packet filtered")
+            || message.contains("no route to host")
+            || message.contains("frag needed")
+        {
+            Some(PingResult::Unreachable(reply))
+        } else if message.contains("time to live exceeded") {
+            Some(PingResult::TtlExceeded(reply))
+        } else if message.starts_with("redirect") {
+            Some(PingResult::Redirect(reply))
+        } else {
+            None
+        }
+    }
+}
+
+/// The details of a single echo reply. Anything the backend doesn't report is `None`.
+#[derive(Debug, Clone)]
+pub struct PingReply {
+    pub seq: Option<u64>,
+    pub ttl: Option<u8>,
+    /// Size of the reply in bytes, as reported by `ping`.
+    pub bytes: Option<usize>,
+    /// The address that sent the reply.
+    pub from: Option<IpAddr>,
+    pub received: SystemTime,
+    /// Another reply to an echo request that was already answered, e.g. marked `(DUP!)`.
+    pub duplicate: bool,
+    /// The reply arrived after its request had already been reported as a timeout.
+    pub late: bool,
+    /// The reply arrived after the reply to a later request.
+    pub reordered: bool,
+    /// The target answered but refused the connection, e.g. with a TCP reset.
+    pub closed: bool,
+    /// Where the time went, for HTTP requests.
+    pub phases: Option<http::Phases>,
+    /// The raw line this reply was parsed from.
+    pub line: String,
+}
+
+impl PingReply {
+    pub fn new(line: String) -> PingReply {
+        PingReply {
+            seq: None,
+            ttl: None,
+            bytes: None,
+            from: None,
+            received: SystemTime::now(),
+            duplicate: false,
+            late: false,
+            reordered: false,
+            closed: false,
+            phases: None,
+            line,
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum PingResult {
-    Pong(Duration, String),
+    Pong(Duration, PingReply),
     Timeout(String),
     Unknown(String),
+    /// An ICMP destination unreachable error, including those for administratively prohibited
+    /// or filtered destinations. `from` is the router that reported it, when known.
+    Unreachable(PingReply),
+    /// The TTL ran out before the echo request reached the target.
+    TtlExceeded(PingReply),
+    /// A router told us to use a different gateway. The echo request may still be answered.
+    Redirect(PingReply),
+    /// An HTTP request was answered with a status outside 2xx.
+    HttpError(u16, PingReply),
+    /// A DNS query was answered with an RCODE other than `NOERROR`.
+    DnsError(u8, PingReply),
+    /// The totals `ping` printed when it exited.
+    Summary(PingSummary),
+    /// Pinging carries on, but not quite as asked, e.g. because the interval had to be raised.
+    Warning(String),
+    /// Pinging failed, with whatever was written to stderr and the exit status of `ping` if
+    /// there was one. No more results follow this.
+    Error(String, Option<ExitStatus>),
 }
 
 impl fmt::Display for PingResult {
@@ -119,6 +328,16 @@ impl fmt::Display for PingResult {
             PingResult::Pong(duration, _) => write!(f, "{:?}", duration),
             PingResult::Timeout(_) => write!(f, "Timeout"),
             PingResult::Unknown(_) => write!(f, "Unknown"),
+            PingResult::Unreachable(_) => write!(f, "Unreachable"),
+            PingResult::TtlExceeded(_) => write!(f, "TTL exceeded"),
+            PingResult::Redirect(_) => write!(f, "Redirect"),
+            PingResult::HttpError(status, _) => write!(f, "HTTP {}", status),
+            PingResult::DnsError(rcode, _) => write!(f, "DNS {}", dns::rcode_name(*rcode)),
+            PingResult::Summary(summary) => write!(f, "{}", summary),
+            PingResult::Warning(warning) => write!(f, "{}", warning),
+            PingResult::Error(stderr, _) if !stderr.is_empty() => write!(f, "{}", stderr),
+            PingResult::Error(_, Some(status)) => write!(f, "ping failed: {}", status),
+            PingResult::Error(_, None) => write!(f, "ping failed"),
         }
     }
 }
@@ -129,55 +348,661 @@ pub enum PingError {
     UnsupportedOS(String),
     #[error("Invalid or unresolvable hostname {0}")]
     HostnameError(String),
+    #[error("Invalid URL {0}")]
+    InvalidUrl(String),
+    #[error("Invalid DNS query {0}")]
+    InvalidQuery(String),
+    #[error("Unsupported ping backend {0}")]
+    UnsupportedBackend(String),
+    #[error("Ping failed: {0}")]
+    Failed(String),
+    #[error("Failed to run {0}: {1}")]
+    SpawnFailed(String, #[source] std::io::Error),
 }
 
-/// Start pinging a an address. The address can be either a hostname or an IP address.
-pub fn ping(addr: String) -> Result<mpsc::Receiver<PingResult>> {
-    ping_with_interval(addr, Duration::from_millis(200))
+/// A running ping, which results can be received from. The `ping` process (or the thread doing
+/// the pinging) is stopped when the handle is dropped.
+pub struct PingHandle {
+    receiver: mpsc::Receiver<PingResult>,
+    // Results from before pinging started, which come out first
+    pending: Mutex<VecDeque<PingResult>>,
+    child: Option<Arc<Mutex<Child>>>,
+    stop: Arc<AtomicBool>,
 }
 
-/// Start pinging a an address. The address can be either a hostname or an IP address.
-pub fn ping_with_interval(addr: String, interval: Duration) -> Result<mpsc::Receiver<PingResult>> {
-    let os_type = os_info::get().os_type();
-    match os_type {
+impl PingHandle {
+    /// Wraps the results of a pinger running in a thread, which should exit once `stop` is set.
+    pub fn new(receiver: mpsc::Receiver<PingResult>, stop: Arc<AtomicBool>) -> PingHandle {
+        PingHandle {
+            receiver,
+            pending: Mutex::new(VecDeque::new()),
+            child: None,
+            stop,
+        }
+    }
+
+    fn with_child(
+        receiver: mpsc::Receiver<PingResult>,
+        child: Arc<Mutex<Child>>,
+        stop: Arc<AtomicBool>,
+    ) -> PingHandle {
+        PingHandle {
+            receiver,
+            pending: Mutex::new(VecDeque::new()),
+            child: Some(child),
+            stop,
+        }
+    }
+
+    fn with_pending(self, result: PingResult) -> PingHandle {
+        self.pending.lock().unwrap().push_back(result);
+        self
+    }
+
+    fn take_pending(&self) -> Option<PingResult> {
+        self.pending.lock().unwrap().pop_front()
+    }
+
+    pub fn recv(&self) -> Result<PingResult, mpsc::RecvError> {
+        match self.take_pending() {
+            Some(result) => Ok(result),
+            None => self.receiver.recv(),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<PingResult, mpsc::RecvTimeoutError> {
+        match self.take_pending() {
+            Some(result) => Ok(result),
+            None => self.receiver.recv_timeout(timeout),
+        }
+    }
+
+    pub fn try_recv(&self) -> Result<PingResult, mpsc::TryRecvError> {
+        match self.take_pending() {
+            Some(result) => Ok(result),
+            None => self.receiver.try_recv(),
+        }
+    }
+
+    /// Stops pinging, killing and reaping the `ping` process if there is one.
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+        if let Some(child) = self.child.take() {
+            let mut child = child.lock().unwrap();
+            // This fails if the process has already exited, which is fine.
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Iterator for PingHandle {
+    type Item = PingResult;
+
+    fn next(&mut self) -> Option<PingResult> {
+        self.recv().ok()
+    }
+}
+
+impl Drop for PingHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// Options controlling how a target is pinged. Options left unset use the defaults of the
+/// underlying `ping`, and options a backend can't express are ignored.
+/// ```
+/// use pinger::PingOptions;
+/// use std::time::Duration;
+///
+/// let options = PingOptions::new()
+///     .interval(Duration::from_millis(500))
+///     .count(10)
+///     .ttl(32);
+/// ```
+#[derive(Debug, Clone)]
+pub struct PingOptions {
+    /// Time between each probe.
+    pub interval: Duration,
+    /// Number of probes to send before stopping.
+    pub count: Option<u32>,
+    /// Size of the probe payload in bytes. Not used for TCP, HTTP or DNS.
+    pub size: Option<usize>,
+    /// Only used for ICMP.
+    pub ttl: Option<u8>,
+    /// How long to wait for each reply.
+    pub timeout: Option<Duration>,
+    /// How long to run for before stopping, regardless of how many probes were sent.
+    pub deadline: Option<Duration>,
+    /// Interface to send probes from. Only used for ICMP.
+    pub interface: Option<String>,
+    /// Address to send probes from. Not used for TCP or HTTP.
+    pub source: Option<IpAddr>,
+    /// Set the don't fragment bit on probes. Only used for ICMP.
+    pub dont_fragment: bool,
+    /// Force the use of IPv4 or IPv6.
+    pub ip_version: Option<IpVersion>,
+    /// Force a backend rather than detecting one.
+    pub backend: Option<Backend>,
+    /// The `ping` program to run instead of the one on the `PATH`, e.g. a setcap'd copy.
+    pub program: Option<String>,
+    /// A command to run `ping` under, e.g. `ip netns exec blue`.
+    pub prefix: Vec<String>,
+    /// Arguments passed to `ping` as they are, after the ones these options are turned into.
+    pub extra_args: Vec<String>,
+}
+
+impl Default for PingOptions {
+    fn default() -> Self {
+        PingOptions {
+            interval: Duration::from_millis(200),
+            count: None,
+            size: None,
+            ttl: None,
+            timeout: None,
+            deadline: None,
+            interface: None,
+            source: None,
+            dont_fragment: false,
+            ip_version: None,
+            backend: None,
+            program: None,
+            prefix: Vec::new(),
+            extra_args: Vec::new(),
+        }
+    }
+}
+
+impl PingOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn count(mut self, count: u32) -> Self {
+        self.count = Some(count);
+        self
+    }
+
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn interface(mut self, interface: impl Into<String>) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    pub fn source(mut self, source: IpAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn dont_fragment(mut self, dont_fragment: bool) -> Self {
+        self.dont_fragment = dont_fragment;
+        self
+    }
+
+    pub fn ip_version(mut self, ip_version: IpVersion) -> Self {
+        self.ip_version = Some(ip_version);
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn program(mut self, program: impl Into<String>) -> Self {
+        self.program = Some(program.into());
+        self
+    }
+
+    pub fn prefix<I, S>(mut self, prefix: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.prefix = prefix.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn extra_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Whether `ping` is run differently to how the pinger would run it by itself
+    pub(crate) fn customises_command(&self) -> bool {
+        self.program.is_some() || !self.prefix.is_empty() || !self.extra_args.is_empty()
+    }
+}
+
+// Formats a duration as (possibly fractional) seconds, as most ping implementations expect.
+fn secs(duration: Duration) -> String {
+    format!("{}", duration.as_secs_f64())
+}
+
+// Rounds a duration up to whole seconds, for flags that don't accept fractions.
+fn whole_secs(duration: Duration) -> String {
+    let secs = duration.as_secs() + (duration.subsec_nanos() > 0) as u64;
+    secs.to_string()
+}
+
+// The first of the addresses that's of the IP version asked for.
+pub(crate) fn pick_addr(
+    addrs: impl IntoIterator<Item = SocketAddr>,
+    version: Option<IpVersion>,
+) -> Option<SocketAddr> {
+    addrs.into_iter().find(|addr| match version {
+        Some(IpVersion::V4) => addr.is_ipv4(),
+        Some(IpVersion::V6) => addr.is_ipv6(),
+        None => true,
+    })
+}
+
+// Looks up a host with the given port, or a `host:port` without one.
+pub(crate) fn resolve(
+    target: &str,
+    port: Option<u16>,
+    version: Option<IpVersion>,
+) -> Result<SocketAddr> {
+    let addrs = match port {
+        Some(port) => (target, port).to_socket_addrs(),
+        None => target.to_socket_addrs(),
+    };
+    addrs
+        .ok()
+        .and_then(|addrs| pick_addr(addrs, version))
+        .ok_or_else(|| PingError::HostnameError(target.to_string()).into())
+}
+
+// Runs `probe` on a thread with sequence numbers counting up from 1, an interval apart, sending
+// what it returns until the count or deadline runs out or the handle is stopped or dropped.
+pub(crate) fn probe_every_interval(
+    options: PingOptions,
+    mut probe: impl FnMut(u64) -> PingResult + Send + 'static,
+) -> PingHandle {
+    let (tx, rx) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+
+    thread::spawn(move || {
+        let started = Instant::now();
+        for seq in 1..=options.count.unwrap_or(u32::MAX) as u64 {
+            if stopped.load(Ordering::Acquire) {
+                break;
+            }
+            if let Some(limit) = options.deadline {
+                if started.elapsed() >= limit {
+                    break;
+                }
+            }
+            let sent = Instant::now();
+            if tx.send(probe(seq)).is_err() {
+                break;
+            }
+            if let Some(remaining) = options.interval.checked_sub(sent.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    });
+
+    PingHandle::new(rx, stop)
+}
+
+// A connected socket for `probe_outstanding`, which sends the probes and reads their replies.
+pub(crate) trait ProbeSocket: Send + 'static {
+    // A failed send should be left to show up as a timeout
+    fn send(&mut self, seq: u64);
+
+    // Reads one packet, matching it against the probes in `outstanding`. `Ok(None)` is something
+    // that wasn't a reply to us, and `WouldBlock` or `TimedOut` means nothing arrived.
+    fn receive(&mut self, outstanding: &mut Outstanding) -> io::Result<Option<PingResult>>;
+
+    fn set_read_timeout(&self, wait: Duration) -> io::Result<()>;
+
+    fn timed_out(&self, seq: u64) -> PingResult;
+}
+
+// Sends a probe on `socket` every interval on a thread, whether or not the last one was answered,
+// until the count or deadline runs out and nothing is outstanding, or the handle is stopped or
+// dropped. Probes without a reply within `timeout` are reported as timed out.
+pub(crate) fn probe_outstanding(
+    options: PingOptions,
+    timeout: Duration,
+    mut socket: impl ProbeSocket,
+) -> PingHandle {
+    let (tx, rx) = mpsc::channel();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&stop);
+
+    thread::spawn(move || {
+        let started = Instant::now();
+        let count = options.count.map(u64::from);
+        let mut outstanding = Outstanding::new();
+        let mut seq = 0;
+        let mut next_send = started;
+        let mut messages = Vec::new();
+        loop {
+            if stopped.load(Ordering::Acquire) {
+                break;
+            }
+            let now = Instant::now();
+            let sending = count.is_none_or(|count| seq < count)
+                && options
+                    .deadline
+                    .is_none_or(|limit| now.duration_since(started) < limit);
+            if !sending && outstanding.is_empty() {
+                break;
+            }
+            if sending && now >= next_send {
+                seq += 1;
+                socket.send(seq);
+                outstanding.sent(seq, now);
+                next_send += options.interval;
+            }
+
+            for seq in outstanding.expire(Instant::now() - timeout) {
+                messages.push(socket.timed_out(seq));
+            }
+
+            // Wait for a reply until there's something else to do
+            let oldest = outstanding.oldest().map(|sent| sent + timeout);
+            let wake = match (sending, oldest) {
+                (true, Some(oldest)) => next_send.min(oldest),
+                (true, None) => next_send,
+                (false, Some(oldest)) => oldest,
+                (false, None) => Instant::now(),
+            };
+            let wait = wake
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1));
+            let _ = socket.set_read_timeout(wait);
+            if let Ok(Some(result)) = socket.receive(&mut outstanding) {
+                messages.push(result);
+            }
+            if !messages.drain(..).all(|message| tx.send(message).is_ok()) {
+                break;
+            }
+        }
+    });
+
+    PingHandle::new(rx, stop)
+}
+
+/// Start pinging an address, which can be either a hostname or an IP address, with the default
+/// options.
+pub fn ping(addr: String) -> Result<PingHandle> {
+    ping_with_options(addr, PingOptions::default())
+}
+
+/// Start pinging an address every `interval`, leaving the other options at their defaults.
+pub fn ping_with_interval(addr: String, interval: Duration) -> Result<PingHandle> {
+    ping_with_options(addr, PingOptions::new().interval(interval))
+}
+
+/// Start pinging an address with the given options, which also pick the backend to ping with.
+pub fn ping_with_options(addr: String, options: PingOptions) -> Result<PingHandle> {
+    let backend = Backend::detect(&options)?;
+    start_backend(backend, addr, options)
+}
+
+/// Ping an address `count` times, waiting for ping to finish, and return the statistics.
+pub fn ping_count(addr: String, count: u32, options: PingOptions) -> Result<PingStats> {
+    let handle = ping_with_options(addr, options.count(count))?;
+    let mut collector = StatsCollector::new();
+    for result in handle {
+        if let PingResult::Error(..) = result {
+            // Ping exits with an error when nothing was answered, which we report as loss
+            if collector.stats().sent == 0 {
+                return Err(PingError::Failed(result.to_string()).into());
+            }
+            break;
+        }
+        collector.add(&result);
+    }
+    Ok(collector.stats())
+}
+
+/// Ping an address once, returning the round trip time or `None` if there was no reply.
+pub fn ping_once(addr: String, options: PingOptions) -> Result<Option<Duration>> {
+    Ok(ping_count(addr, 1, options)?.avg)
+}
+
+/// Raises the interval to the shortest one the backend's `ping` accepts from us, as it would
+/// otherwise refuse to start. Returns a warning saying so if it had to.
+pub(crate) fn clamp_interval(backend: &Backend, options: &mut PingOptions) -> Option<String> {
+    let min = backend.min_interval()?;
+    // There's no telling who a prefix like sudo runs ping as
+    if options.interval >= min || !options.prefix.is_empty() || backend::privileged() {
+        return None;
+    }
+    let warning = format!(
+        "Raised the interval from {}s to {}s, as only root can ping more often",
+        secs(options.interval),
+        secs(min)
+    );
+    options.interval = min;
+    Some(warning)
+}
+
+/// The interval pings will really be sent at with these options, which is longer than asked for
+/// when only root can ping that often. A warning comes out of the handle when that's the case.
+pub fn effective_interval(options: &PingOptions) -> Duration {
+    let mut options = options.clone();
+    if let Ok(backend) = Backend::detect(&options) {
+        let (backend, _) = resolve_custom(backend, &options);
+        clamp_interval(&backend, &mut options);
+    }
+    options.interval
+}
+
+// Custom programs are run like whichever `ping` they turn out to be, returning that along with
+// the program to run, if it isn't the usual one
+pub(crate) fn resolve_custom(backend: Backend, options: &PingOptions) -> (Backend, Option<String>) {
+    match backend {
+        Backend::Custom(program) => {
+            let backend = Backend::probe(&program).unwrap_or(Backend::Iputils);
+            (backend, Some(program))
+        }
+        backend => (backend, options.program.clone()),
+    }
+}
+
+pub(crate) fn start_backend(
+    backend: Backend,
+    addr: String,
+    mut options: PingOptions,
+) -> Result<PingHandle> {
+    let (backend, program) = resolve_custom(backend, &options);
+    let warning = clamp_interval(&backend, &mut options);
+    let handle = match backend {
         #[cfg(windows)]
-        Type::Windows => {
-            let mut p = windows::WindowsPinger::default();
-            p.set_interval(interval);
-            p.start::<windows::WindowsParser>(addr)
-        }
-        Type::Amazon
-        | Type::Arch
-        | Type::CentOS
-        | Type::Debian
-        | Type::EndeavourOS
-        | Type::Fedora
-        | Type::Linux
-        | Type::Manjaro
-        | Type::Mint
-        | Type::openSUSE
-        | Type::OracleLinux
-        | Type::Redhat
-        | Type::RedHatEnterprise
-        | Type::SUSE
-        | Type::Ubuntu
-        | Type::Pop
-        | Type::Solus
-        | Type::Android => {
-            let mut p = linux::LinuxPinger::default();
-            p.set_interval(interval);
-            p.start::<linux::LinuxParser>(addr)
-        }
-        Type::Alpine => {
-            let mut p = linux::AlpinePinger::default();
-            p.set_interval(interval);
-            p.start::<linux::LinuxParser>(addr)
-        }
-        Type::Macos => {
-            let mut p = macos::MacOSPinger::default();
-            p.set_interval(interval);
-            p.start::<macos::MacOSParser>(addr)
-        }
-        _ => Err(PingError::UnsupportedOS(os_type.to_string()).into()),
+        Backend::Windows => start_native::<_, windows::WindowsParser>(
+            &backend,
+            windows::WindowsPinger::default(),
+            addr,
+            options,
+        ),
+        #[cfg(target_os = "linux")]
+        Backend::NativeSocket => start_native::<_, linux::LinuxParser>(
+            &backend,
+            linux::IcmpSocketPinger::default(),
+            addr,
+            options,
+        ),
+        Backend::Iputils => start_with::<_, linux::LinuxParser>(
+            linux::LinuxPinger::default(),
+            program,
+            addr,
+            options,
+        ),
+        Backend::Busybox => start_with::<_, linux::LinuxParser>(
+            linux::AlpinePinger::default(),
+            program,
+            addr,
+            options,
+        ),
+        Backend::Inetutils => start_with::<_, linux::LinuxParser>(
+            linux::InetutilsPinger::default(),
+            program,
+            addr,
+            options,
+        ),
+        Backend::MacOS => start_with::<_, macos::MacOSParser>(
+            macos::MacOSPinger::default(),
+            program,
+            addr,
+            options,
+        ),
+        Backend::Bsd(variant) => {
+            start_with::<_, bsd::BsdParser>(bsd::BsdPinger::new(variant), program, addr, options)
+        }
+        backend => Err(PingError::UnsupportedBackend(backend.to_string()).into()),
+    }?;
+    Ok(match warning {
+        Some(warning) => handle.with_pending(PingResult::Warning(warning)),
+        None => handle,
+    })
+}
+
+// Backends that don't run `ping` can't run it any differently either, and would otherwise
+// quietly fall back to running the system one.
+#[cfg(any(windows, target_os = "linux"))]
+pub(crate) fn check_native(backend: &Backend, options: &PingOptions) -> Result<()> {
+    if options.customises_command() {
+        return Err(PingError::UnsupportedBackend(format!(
+            "{} with a ping program, prefix or extra arguments",
+            backend
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(any(windows, target_os = "linux"))]
+pub(crate) fn start_native<T, P>(
+    backend: &Backend,
+    mut pinger: T,
+    addr: String,
+    options: PingOptions,
+) -> Result<PingHandle>
+where
+    T: Pinger,
+    P: Parser,
+{
+    check_native(backend, &options)?;
+    pinger.set_options(options);
+    pinger.start::<P>(addr)
+}
+
+fn start_with<T, P>(
+    pinger: T,
+    program: Option<String>,
+    addr: String,
+    options: PingOptions,
+) -> Result<PingHandle>
+where
+    T: Pinger,
+    P: Parser,
+{
+    command(pinger, program, options).start::<P>(addr)
+}
+
+// Sets up `pinger` to run as the options ask, which is as it is unless they customise the command.
+pub(crate) fn command<T: Pinger>(
+    pinger: T,
+    program: Option<String>,
+    options: PingOptions,
+) -> CustomCommand<T> {
+    let mut command = CustomCommand {
+        pinger,
+        program,
+        prefix: options.prefix.clone(),
+        extra_args: options.extra_args.clone(),
+    };
+    command.set_options(options);
+    command
+}
+
+// Runs a different program in place of the one a pinger normally uses, under a prefix and with
+// extra arguments. With a prefix, the program is the first word of it and `ping` is an argument.
+#[derive(Default)]
+pub(crate) struct CustomCommand<T> {
+    pinger: T,
+    program: Option<String>,
+    prefix: Vec<String>,
+    extra_args: Vec<String>,
+}
+
+impl<T: Pinger> CustomCommand<T> {
+    fn ping_program(&self) -> &str {
+        self.program
+            .as_deref()
+            .unwrap_or_else(|| self.pinger.program())
+    }
+}
+
+impl<T: Pinger> Pinger for CustomCommand<T> {
+    fn set_interval(&mut self, interval: Duration) {
+        self.pinger.set_interval(interval);
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.pinger.set_options(options);
+    }
+
+    fn program(&self) -> &str {
+        match self.prefix.first() {
+            Some(program) => program,
+            None => self.ping_program(),
+        }
+    }
+
+    fn ping_args(&self, target: String) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some((_, prefix_args)) = self.prefix.split_first() {
+            args.extend(prefix_args.iter().cloned());
+            args.push(self.ping_program().to_string());
+        }
+        args.extend(self.pinger.ping_args(target));
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    fn seq_tracker(&self) -> Option<SeqTracker> {
+        self.pinger.seq_tracker()
     }
 }
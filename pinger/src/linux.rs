@@ -1,54 +1,421 @@
-use crate::{Parser, PingResult, Pinger};
+use crate::summary::SummaryParser;
+use crate::tracker::{SeqTracker, DEFAULT_GRACE};
+use crate::{secs, whole_secs, IpVersion, Parser, PingOptions, PingResult, Pinger};
 use regex::Regex;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use crate::tracker::Outstanding;
+#[cfg(target_os = "linux")]
+use crate::{icmp, probe_outstanding, resolve, PingHandle, PingReply, ProbeSocket};
+#[cfg(target_os = "linux")]
+use anyhow::Result;
+#[cfg(target_os = "linux")]
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::net::{IpAddr, SocketAddr};
+#[cfg(all(target_os = "linux", feature = "async"))]
+use std::os::unix::io::{AsRawFd, RawFd};
+
+// How long the socket pinger waits for a reply before reporting a timeout
+#[cfg(target_os = "linux")]
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Default)]
 pub struct LinuxPinger {
-    interval: Duration,
+    options: PingOptions,
 }
 
 impl Pinger for LinuxPinger {
     fn ping_args(&self, target: String) -> Vec<String> {
+        let options = &self.options;
         // The -O flag ensures we "no answer yet" messages from ping
         // See https://superuser.com/questions/270083/linux-ping-show-time-out
-        vec![
-            "-O".to_string(),
-            format!("-i{:.1}", self.interval.as_millis() as f32 / 1_000_f32),
-            target,
-        ]
+        let mut args = vec!["-O".to_string(), format!("-i{}", secs(options.interval))];
+        if let Some(count) = options.count {
+            args.push(format!("-c{}", count));
+        }
+        if let Some(size) = options.size {
+            args.push(format!("-s{}", size));
+        }
+        if let Some(ttl) = options.ttl {
+            args.push(format!("-t{}", ttl));
+        }
+        // iputils before 20210202 only accepts whole seconds
+        if let Some(timeout) = options.timeout {
+            args.push(format!("-W{}", whole_secs(timeout)));
+        }
+        if let Some(deadline) = options.deadline {
+            args.push(format!("-w{}", whole_secs(deadline)));
+        }
+        // iputils takes either an interface or a source address with -I, not both
+        if let Some(interface) = &options.interface {
+            args.push(format!("-I{}", interface));
+        } else if let Some(source) = options.source {
+            args.push(format!("-I{}", source));
+        }
+        if options.dont_fragment {
+            args.push("-Mdo".to_string());
+        }
+        match options.ip_version {
+            Some(IpVersion::V4) => args.push("-4".to_string()),
+            Some(IpVersion::V6) => args.push("-6".to_string()),
+            None => {}
+        }
+        args.push(target);
+        args
     }
 
     fn set_interval(&mut self, interval: Duration) {
-        self.interval = interval;
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
+
+// Finds the timeouts that busybox and inetutils don't report. Both wait for the last reply for up
+// to -W seconds.
+fn missing_timeouts_tracker(options: &PingOptions) -> SeqTracker {
+    let grace = options.timeout.unwrap_or_default().max(DEFAULT_GRACE);
+    let tracker = SeqTracker::new(options.interval, grace, Instant::now());
+    match options.count {
+        Some(count) => tracker.count(count),
+        None => tracker,
     }
 }
 
 #[derive(Default)]
 pub struct AlpinePinger {
-    interval: Duration,
+    options: PingOptions,
 }
 
-// Alpine doesn't support timeout notifications, so we don't add the -O flag here
+// Alpine doesn't support timeout notifications, so we don't add the -O flag here and track
+// sequence numbers to find them instead
 impl Pinger for AlpinePinger {
+    fn ping_args(&self, target: String) -> Vec<String> {
+        let options = &self.options;
+        let mut args = vec![format!("-i{}", secs(options.interval))];
+        if let Some(count) = options.count {
+            args.push(format!("-c{}", count));
+        }
+        if let Some(size) = options.size {
+            args.push(format!("-s{}", size));
+        }
+        if let Some(ttl) = options.ttl {
+            args.push(format!("-t{}", ttl));
+        }
+        // Busybox only waits for whole seconds. There's no way to set the don't fragment bit.
+        if let Some(timeout) = options.timeout {
+            args.push(format!("-W{}", whole_secs(timeout)));
+        }
+        if let Some(deadline) = options.deadline {
+            args.push(format!("-w{}", whole_secs(deadline)));
+        }
+        if let Some(interface) = &options.interface {
+            args.push(format!("-I{}", interface));
+        } else if let Some(source) = options.source {
+            args.push(format!("-I{}", source));
+        }
+        match options.ip_version {
+            Some(IpVersion::V4) => args.push("-4".to_string()),
+            Some(IpVersion::V6) => args.push("-6".to_string()),
+            None => {}
+        }
+        args.push(target);
+        args
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+
+    fn seq_tracker(&self) -> Option<SeqTracker> {
+        Some(missing_timeouts_tracker(&self.options))
+    }
+}
+
+#[derive(Default)]
+pub struct InetutilsPinger {
+    options: PingOptions,
+}
+
+impl InetutilsPinger {
+    fn ipv6(&self) -> bool {
+        self.options.ip_version == Some(IpVersion::V6)
+    }
+}
+
+// GNU inetutils has no timeout notifications either, and can't pick the interface or source
+// address, or set the don't fragment bit.
+impl Pinger for InetutilsPinger {
+    fn program(&self) -> &str {
+        if self.ipv6() {
+            "ping6"
+        } else {
+            "ping"
+        }
+    }
+
+    fn ping_args(&self, target: String) -> Vec<String> {
+        let options = &self.options;
+        let mut args = vec![format!("-i{}", secs(options.interval))];
+        if let Some(count) = options.count {
+            args.push(format!("-c{}", count));
+        }
+        if let Some(size) = options.size {
+            args.push(format!("-s{}", size));
+        }
+        if let Some(ttl) = options.ttl {
+            args.push(format!("--ttl={}", ttl));
+        }
+        if let Some(timeout) = options.timeout {
+            args.push(format!("-W{}", whole_secs(timeout)));
+        }
+        if let Some(deadline) = options.deadline {
+            args.push(format!("-w{}", whole_secs(deadline)));
+        }
+        args.push(target);
+        args
+    }
+
     fn set_interval(&mut self, interval: Duration) {
-        self.interval = interval;
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+
+    fn seq_tracker(&self) -> Option<SeqTracker> {
+        Some(missing_timeouts_tracker(&self.options))
+    }
+}
+
+/// Pings using ICMP sockets directly rather than running the `ping` binary.
+///
+/// Unprivileged `SOCK_DGRAM` ICMP sockets are tried first (these need the group to be allowed by
+/// `net.ipv4.ping_group_range`), falling back to a raw socket when we have CAP_NET_RAW.
+/// Requests go out every interval whether or not the last one was answered, and replies that
+/// turn up after their timeout are marked [`PingReply::late`].
+#[cfg(target_os = "linux")]
+#[derive(Default)]
+pub struct IcmpSocketPinger {
+    options: PingOptions,
+}
+
+#[cfg(target_os = "linux")]
+impl IcmpSocketPinger {
+    fn open_socket(&self, target: IpAddr) -> Result<(Socket, bool)> {
+        let (domain, protocol) = match target {
+            IpAddr::V4(_) => (Domain::IPV4, Protocol::ICMPV4),
+            IpAddr::V6(_) => (Domain::IPV6, Protocol::ICMPV6),
+        };
+        let (socket, raw) = match Socket::new(domain, Type::DGRAM, Some(protocol)) {
+            Ok(socket) => (socket, false),
+            Err(_) => (Socket::new(domain, Type::RAW, Some(protocol))?, true),
+        };
+        let options = &self.options;
+        if let Some(interface) = &options.interface {
+            socket.bind_device(Some(interface.as_bytes()))?;
+        }
+        if let Some(source) = options.source {
+            socket.bind(&SockAddr::from(SocketAddr::new(source, 0)))?;
+        }
+        if let Some(ttl) = options.ttl {
+            match target {
+                IpAddr::V4(_) => socket.set_ttl(ttl as u32)?,
+                IpAddr::V6(_) => socket.set_unicast_hops_v6(ttl as u32)?,
+            }
+        }
+        if options.dont_fragment {
+            icmp::set_dont_fragment(&socket, target)?;
+        }
+        // Connecting makes the kernel drop packets from anyone other than the target.
+        socket.connect(&SockAddr::from(SocketAddr::new(target, 0)))?;
+        icmp::enable_ttl(&socket, target)?;
+        Ok((socket, raw))
+    }
+
+    // A socket to probe `target` with, and how long to wait for each reply. Resolving the target
+    // blocks.
+    pub(crate) fn probes(&self, target: &str) -> Result<(IcmpProbes, Duration)> {
+        let options = &self.options;
+        let addr = resolve(target, Some(0), options.ip_version)?.ip();
+        let (socket, raw) = self.open_socket(addr)?;
+        let payload_size = options.size.unwrap_or(icmp::PAYLOAD_SIZE);
+        let probes = IcmpProbes {
+            socket,
+            target: addr,
+            raw,
+            ident: std::process::id() as u16,
+            payload_size,
+            buffer: vec![0u8; payload_size + 1500],
+            latest: 0,
+        };
+        Ok((probes, options.timeout.unwrap_or(DEFAULT_TIMEOUT)))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) struct IcmpProbes {
+    socket: Socket,
+    target: IpAddr,
+    raw: bool,
+    ident: u16,
+    payload_size: usize,
+    buffer: Vec<u8>,
+    latest: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl ProbeSocket for IcmpProbes {
+    fn send(&mut self, seq: u64) {
+        // ICMP sequence numbers wrap around
+        let request = icmp::echo_request(self.target, self.ident, seq as u16, self.payload_size);
+        let _ = self.socket.send(&request);
+        self.latest = seq;
+    }
+
+    fn receive(&mut self, outstanding: &mut Outstanding) -> io::Result<Option<PingResult>> {
+        let (size, ttl) = icmp::recv(&self.socket, &mut self.buffer)?;
+        Ok(self.reply(size, ttl, Instant::now(), outstanding))
+    }
+
+    fn set_read_timeout(&self, wait: Duration) -> io::Result<()> {
+        self.socket.set_read_timeout(Some(wait))
+    }
+
+    fn timed_out(&self, seq: u64) -> PingResult {
+        PingResult::Timeout(format!("no answer yet for icmp_seq={}", seq as u16))
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "async"))]
+impl AsRawFd for IcmpProbes {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl IcmpProbes {
+    // For driving the socket with tokio instead of blocking reads
+    #[cfg(feature = "async")]
+    pub(crate) fn set_nonblocking(&self) -> io::Result<()> {
+        self.socket.set_nonblocking(true)
+    }
+
+    // The result for a packet of `size` in the buffer, if it's an echo reply to one of our probes
+    fn reply(
+        &self,
+        size: usize,
+        ttl: Option<u8>,
+        received: Instant,
+        outstanding: &mut Outstanding,
+    ) -> Option<PingResult> {
+        // Raw IPv6 sockets never include the IP header.
+        let has_ip_header = self.raw && self.target.is_ipv4();
+        // Datagram sockets have their identifier rewritten by the kernel, which also filters
+        // replies for us. Raw sockets see every echo reply so we need to check our own.
+        let echo = icmp::parse_echo_reply(&self.buffer[..size], self.target, has_ip_header)?;
+        if self.raw && echo.ident != self.ident {
+            return None;
+        }
+        // Replies can only be for something we've sent, so count back from the latest request
+        let behind = (self.latest as u16).wrapping_sub(echo.seq);
+        let seq = self.latest.checked_sub(behind as u64)?;
+        let (duration, reply) = outstanding.reply(seq, received)?;
+        let ttl = echo.ttl.or(ttl);
+        let line = format!(
+            "{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+            echo.size,
+            self.target,
+            echo.seq,
+            ttl.map_or("?".to_string(), |ttl| ttl.to_string()),
+            duration.as_micros() as f64 / 1_000f64
+        );
+        Some(PingResult::Pong(
+            duration,
+            PingReply {
+                seq: Some(seq),
+                ttl,
+                bytes: Some(echo.size),
+                from: Some(self.target),
+                line,
+                ..reply
+            },
+        ))
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Pinger for IcmpSocketPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let (probes, timeout) = self.probes(&target)?;
+        Ok(probe_outstanding(self.options.clone(), timeout, probes))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
     }
 }
 
 lazy_static! {
-    static ref UBUNTU_RE: Regex = Regex::new(r"(?i-u)time=(?P<time>\d+(?:\.\d+)?) *ms").unwrap();
+    static ref UBUNTU_RE: Regex = Regex::new(
+        r"(?i)^(?P<bytes>\d+) bytes from (?:\S+ \()?(?P<from>[^\s()]+?)\)?: (?:icmp_)?seq=(?P<seq>\d+)(?: ttl=(?P<ttl>\d+))?.*time=(?P<time>\d+(?:\.\d+)?) *ms"
+    )
+    .unwrap();
+    // Errors reported by a router along the way (or our own host), e.g.
+    // "From 192.168.1.1 icmp_seq=3 Destination Host Unreachable"
+    static ref ICMP_ERROR_RE: Regex = Regex::new(
+        r"^From (?:\S+ \()?(?P<from>[^\s()]+?)\)?:? icmp_seq=(?P<seq>\d+) (?P<message>.+)$"
+    )
+    .unwrap();
+    // Busybox and iputils report local routing failures on sending, e.g.
+    // "ping: sendto: Network unreachable"
+    static ref SEND_ERROR_RE: Regex =
+        Regex::new(r"^ping: send(?:to|msg): (?P<message>.+)$").unwrap();
 }
 
 #[derive(Default)]
-pub struct LinuxParser {}
+pub struct LinuxParser {
+    summary: SummaryParser,
+}
 
 impl Parser for LinuxParser {
-    fn parse(&self, line: String) -> Option<PingResult> {
-        if line.starts_with("64 bytes from") {
+    fn parse(&mut self, line: String) -> Option<PingResult> {
+        if let Some(summary) = self.summary.parse(&line) {
+            return summary;
+        }
+        // The size varies with the size option
+        if UBUNTU_RE.is_match(&line) {
             return self.extract_regex(&UBUNTU_RE, line);
         } else if line.starts_with("no answer yet") {
             return Some(PingResult::Timeout(line));
+        } else if line.starts_with("From ") {
+            return self.extract_icmp_error(&ICMP_ERROR_RE, line);
+        } else if line.starts_with("ping: send") {
+            return self.extract_icmp_error(&SEND_ERROR_RE, line);
         }
         None
     }
+
+    fn finish(&mut self) -> Option<PingResult> {
+        self.summary.finish()
+    }
 }
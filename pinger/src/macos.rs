@@ -1,40 +1,113 @@
-use crate::{Parser, PingResult, Pinger};
+use crate::summary::SummaryParser;
+use crate::{secs, whole_secs, IpVersion, Parser, PingOptions, PingResult, Pinger};
 use regex::Regex;
 use std::time::Duration;
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"time=(?:(?P<time>[0-9\.]+)\s+ms)").unwrap();
+    static ref RE: Regex = Regex::new(
+        r"^(?:(?P<bytes>\d+) bytes from (?P<from>[^\s]+?): icmp_seq=(?P<seq>\d+) ttl=(?P<ttl>\d+) )?.*time=(?:(?P<time>[0-9\.]+)\s+ms)"
+    )
+    .unwrap();
+    // e.g. "92 bytes from 192.168.1.1: Destination Host Unreachable", followed by a dump of the
+    // original packet's headers which we ignore.
+    static ref ICMP_ERROR_RE: Regex =
+        Regex::new(r"^\d+ bytes from (?P<from>[^\s]+?): (?P<message>[^=]+)$").unwrap();
 }
 
 #[derive(Default)]
 pub struct MacOSPinger {
-    interval: Duration,
+    options: PingOptions,
+}
+
+impl MacOSPinger {
+    fn ipv6(&self) -> bool {
+        self.options.ip_version == Some(IpVersion::V6)
+    }
 }
 
 impl Pinger for MacOSPinger {
+    // IPv6 needs the separate ping6 binary, which takes a smaller set of flags.
+    fn program(&self) -> &str {
+        if self.ipv6() {
+            "ping6"
+        } else {
+            "ping"
+        }
+    }
+
     fn ping_args(&self, target: String) -> Vec<String> {
-        vec![
-            format!("-i{:.1}", self.interval.as_millis() as f32 / 1_000_f32),
-            target,
-        ]
+        let options = &self.options;
+        let mut args = vec![format!("-i{}", secs(options.interval))];
+        if let Some(count) = options.count {
+            args.push(format!("-c{}", count));
+        }
+        if let Some(size) = options.size {
+            args.push(format!("-s{}", size));
+        }
+        if self.ipv6() {
+            if let Some(ttl) = options.ttl {
+                args.push(format!("-h{}", ttl));
+            }
+            if let Some(interface) = &options.interface {
+                args.push(format!("-I{}", interface));
+            }
+        } else {
+            if let Some(ttl) = options.ttl {
+                args.push(format!("-m{}", ttl));
+            }
+            // macOS takes the per-probe wait in milliseconds
+            if let Some(timeout) = options.timeout {
+                args.push(format!("-W{}", timeout.as_millis()));
+            }
+            if let Some(deadline) = options.deadline {
+                args.push(format!("-t{}", whole_secs(deadline)));
+            }
+            if let Some(interface) = &options.interface {
+                args.push(format!("-b{}", interface));
+            }
+            if options.dont_fragment {
+                args.push("-D".to_string());
+            }
+        }
+        if let Some(source) = options.source {
+            args.push(format!("-S{}", source));
+        }
+        args.push(target);
+        args
     }
 
     fn set_interval(&mut self, interval: Duration) {
-        self.interval = interval;
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
     }
 }
 
 #[derive(Default)]
-pub struct MacOSParser {}
+pub struct MacOSParser {
+    summary: SummaryParser,
+}
 
 impl Parser for MacOSParser {
-    fn parse(&self, line: String) -> Option<PingResult> {
+    fn parse(&mut self, line: String) -> Option<PingResult> {
+        if let Some(summary) = self.summary.parse(&line) {
+            return summary;
+        }
         if line.starts_with("PING ") {
             return None;
         }
         if line.starts_with("Request timeout") {
             return Some(PingResult::Timeout(line));
         }
+        if ICMP_ERROR_RE.is_match(&line) {
+            return self.extract_icmp_error(&ICMP_ERROR_RE, line);
+        }
         self.extract_regex(&RE, line)
     }
+
+    fn finish(&mut self) -> Option<PingResult> {
+        self.summary.finish()
+    }
 }
@@ -0,0 +1,216 @@
+//! Plays back recorded results, for demos, bug reports and tests that need to be deterministic.
+//!
+//! The target is the path of either a captured `ping` transcript, which is read with the parser
+//! the pinger is started with, or a CSV log written by netcheck. Results come out at the pace
+//! they were recorded at, divided by the speed:
+//!
+//! * CSV logs have the time of each row.
+//! * Transcripts from `ping -D` have a timestamp on each line.
+//! * Otherwise transcripts are paced by their sequence numbers, with requests sent every
+//!   interval, so gaps in the sequence stay gaps.
+//!
+//! Transcripts can be the fixtures from this crate's tests, as anything after a `-----` line is
+//! ignored.
+use crate::tracker::result_seq;
+use crate::{Parser, PingError, PingHandle, PingOptions, PingReply, PingResult, Pinger};
+use anyhow::Result;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    // "[1634567890.123456] 64 bytes from ...", from iputils with -D
+    static ref TIMESTAMP_RE: Regex =
+        Regex::new(r"^\[(?P<secs>\d+(?:\.\d+)?)\] (?P<line>.*)$").unwrap();
+}
+
+// The logger counts timeouts and errors as a second
+const CSV_TIMEOUT: Duration = Duration::from_secs(1);
+// How often a replay waiting for its next result checks whether it's been stopped
+const STOP_CHECK: Duration = Duration::from_millis(100);
+
+pub struct ReplayPinger {
+    options: PingOptions,
+    speed: f64,
+    column: usize,
+}
+
+impl Default for ReplayPinger {
+    fn default() -> Self {
+        ReplayPinger {
+            options: PingOptions::default(),
+            speed: 1f64,
+            column: 0,
+        }
+    }
+}
+
+impl ReplayPinger {
+    /// How many times faster than recorded to play results back. Infinity plays them back as
+    /// fast as they can be received.
+    pub fn speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Which target's results to play back from a CSV log, counting from 0.
+    pub fn column(mut self, column: usize) -> Self {
+        self.column = column;
+        self
+    }
+
+    /// The names of the targets recorded in a file: the column headers of a CSV log, or just the
+    /// file name for a transcript.
+    pub fn targets(path: impl AsRef<Path>) -> Result<Vec<String>> {
+        let path = path.as_ref();
+        let contents = read(path)?;
+        match csv_header(&contents) {
+            Some(header) => Ok(header
+                .split(',')
+                .skip(1)
+                .map(|name| name.trim().to_string())
+                .collect()),
+            None => Ok(vec![path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()]),
+        }
+    }
+
+    fn transcript<P: Parser>(&self, contents: &str) -> Vec<(Duration, PingResult)> {
+        let mut parser = P::default();
+        let mut results = Vec::new();
+        let mut first = None;
+        let mut at = Duration::ZERO;
+        // Where sequence numbers are paced from: the last line that had one, and when it was
+        let mut anchor: Option<(Duration, f64)> = None;
+        for line in contents.lines().take_while(|line| line.trim() != "-----") {
+            let (timestamp, line) = match TIMESTAMP_RE.captures(line) {
+                Some(cap) => (cap["secs"].parse::<f64>().ok(), cap["line"].to_string()),
+                None => (None, line.to_string()),
+            };
+            let result = match parser.parse(line) {
+                Some(result) => result,
+                None => continue,
+            };
+            // Anything without a timestamp or sequence number comes out along with what's before
+            // it. Lines with only a sequence number are paced from the last one seen, so that they
+            // stay in order after timestamped lines.
+            let seq = result_seq(&result).map(|seq| seq as f64);
+            if let Some(secs) = timestamp {
+                let start = *first.get_or_insert(secs);
+                at = at.max(Duration::from_secs_f64((secs - start).max(0f64)));
+                anchor = seq.map(|seq| (at, seq));
+            } else if let Some(seq) = seq {
+                let (from, start) = *anchor.get_or_insert((at, seq));
+                at = at.max(from + self.options.interval.mul_f64((seq - start).max(0f64)));
+            }
+            results.push((at, result));
+        }
+        if let Some(result) = parser.finish() {
+            results.push((at, result));
+        }
+        results
+    }
+
+    fn csv(&self, contents: &str) -> Result<Vec<(Duration, PingResult)>> {
+        let mut results = Vec::new();
+        for (row, line) in contents
+            .lines()
+            .skip_while(|line| !line.starts_with("Time,"))
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+        {
+            let mut fields = line.split(',').map(str::trim);
+            let at = fields.next().and_then(|time| time.parse::<f64>().ok());
+            let millis = fields
+                .nth(self.column)
+                .and_then(|millis| millis.parse::<u64>().ok());
+            let (at, millis) = match (at, millis) {
+                (Some(at), Some(millis)) => (at, millis),
+                _ => return Err(PingError::Failed(format!("unexpected CSV row {}", line)).into()),
+            };
+            let duration = Duration::from_millis(millis);
+            let seq = row as u64 + 1;
+            let result = if duration >= CSV_TIMEOUT {
+                PingResult::Timeout(format!("no answer yet for seq={} at {}s", seq, at))
+            } else {
+                PingResult::Pong(
+                    duration,
+                    PingReply {
+                        seq: Some(seq),
+                        ..PingReply::new(format!("seq={} time={} ms at {}s", seq, millis, at))
+                    },
+                )
+            };
+            results.push((Duration::from_secs_f64(at.max(0f64)), result));
+        }
+        Ok(results)
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| PingError::Failed(format!("could not read {}: {}", path.display(), e)).into())
+}
+
+// Summary rows are written above the header, so it isn't always the first line
+fn csv_header(contents: &str) -> Option<&str> {
+    contents.lines().find(|line| line.starts_with("Time,"))
+}
+
+// The options other than the interval don't apply.
+impl Pinger for ReplayPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let contents = read(Path::new(&target))?;
+        let results = match csv_header(&contents) {
+            Some(_) => self.csv(&contents)?,
+            None => self.transcript::<P>(&contents),
+        };
+        let speed = self.speed;
+
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+
+        thread::spawn(move || {
+            let started = Instant::now();
+            for (at, result) in results {
+                if stopped.load(Ordering::Acquire) {
+                    break;
+                }
+                if speed.is_finite() && speed > 0f64 {
+                    // In slices, so that a long gap doesn't hold up stopping
+                    let due = started + at.div_f64(speed);
+                    while let Some(remaining) = due.checked_duration_since(Instant::now()) {
+                        if stopped.load(Ordering::Acquire) {
+                            return;
+                        }
+                        thread::sleep(remaining.min(STOP_CHECK));
+                    }
+                }
+                if tx.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(PingHandle::new(rx, stop))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
@@ -0,0 +1,117 @@
+//! Finds the default gateway from the kernel's routing table, which is much quicker than tracing
+//! the route to somewhere and taking the first hop, and works when the gateway doesn't answer.
+//!
+//! The tables are read from `/proc/net/route` and `/proc/net/ipv6_route` on Linux. The parsers
+//! work anywhere, given their contents.
+use std::fmt;
+use std::fmt::Formatter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// From linux/route.h
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+const RTF_REJECT: u32 = 0x0200;
+
+/// A route that traffic takes when nothing more specific matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefaultRoute {
+    pub gateway: IpAddr,
+    pub interface: String,
+    /// Lower is preferred, e.g. ethernet over wifi.
+    pub metric: u32,
+}
+
+impl fmt::Display for DefaultRoute {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "via {} dev {} metric {}",
+            self.gateway, self.interface, self.metric
+        )
+    }
+}
+
+/// Parses the default routes out of `/proc/net/route`, most preferred first.
+pub fn parse_ipv4_routes(table: &str) -> Vec<DefaultRoute> {
+    // Iface Destination Gateway Flags RefCnt Use Metric Mask MTU Window IRTT
+    let mut routes: Vec<DefaultRoute> = table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let hex = |i: usize| u32::from_str_radix(fields.get(i)?, 16).ok();
+            let (destination, gateway, flags, mask) = (hex(1)?, hex(2)?, hex(3)?, hex(7)?);
+            if destination != 0 || mask != 0 || !is_usable_gateway(flags) {
+                return None;
+            }
+            Some(DefaultRoute {
+                // Addresses are written as the number they'd be in memory, so in the host's
+                // byte order
+                gateway: Ipv4Addr::from(gateway.to_ne_bytes()).into(),
+                interface: fields[0].to_string(),
+                metric: fields.get(6)?.parse().ok()?,
+            })
+        })
+        .collect();
+    routes.sort_by_key(|route| route.metric);
+    routes
+}
+
+/// Parses the default routes out of `/proc/net/ipv6_route`, most preferred first.
+pub fn parse_ipv6_routes(table: &str) -> Vec<DefaultRoute> {
+    // Destination, its prefix length, source, its prefix length, next hop, metric, reference
+    // count, use, flags and interface, with no header
+    let mut routes: Vec<DefaultRoute> = table
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                return None;
+            }
+            let (destination, prefix_len) = (ipv6(fields[0])?, fields[1]);
+            let flags = u32::from_str_radix(fields[8], 16).ok()?;
+            if !destination.is_unspecified() || prefix_len != "00" || !is_usable_gateway(flags) {
+                return None;
+            }
+            let gateway = ipv6(fields[4])?;
+            if gateway.is_unspecified() {
+                return None;
+            }
+            Some(DefaultRoute {
+                gateway: gateway.into(),
+                interface: fields[9].to_string(),
+                metric: u32::from_str_radix(fields[5], 16).ok()?,
+            })
+        })
+        .collect();
+    routes.sort_by_key(|route| route.metric);
+    routes
+}
+
+fn is_usable_gateway(flags: u32) -> bool {
+    flags & RTF_UP != 0 && flags & RTF_GATEWAY != 0 && flags & RTF_REJECT == 0
+}
+
+// e.g. "fe800000000000000000000000000001"
+fn ipv6(hex: &str) -> Option<Ipv6Addr> {
+    if hex.len() != 32 {
+        return None;
+    }
+    u128::from_str_radix(hex, 16).ok().map(Ipv6Addr::from)
+}
+
+/// The default routes of this machine, IPv4 ones first, each most preferred first. Tables that
+/// can't be read are skipped, e.g. `/proc/net/ipv6_route` when IPv6 is disabled.
+#[cfg(target_os = "linux")]
+pub fn default_routes() -> Vec<DefaultRoute> {
+    let read = |path| std::fs::read_to_string(path).unwrap_or_default();
+    let mut routes = parse_ipv4_routes(&read("/proc/net/route"));
+    routes.extend(parse_ipv6_routes(&read("/proc/net/ipv6_route")));
+    routes
+}
+
+/// The route most traffic leaves by, if there is one.
+#[cfg(target_os = "linux")]
+pub fn default_route() -> Option<DefaultRoute> {
+    default_routes().into_iter().next()
+}
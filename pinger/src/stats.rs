@@ -0,0 +1,131 @@
+//! Aggregate statistics over a run of ping results.
+//! ```
+//! use pinger::stats::StatsCollector;
+//! use pinger::{PingReply, PingResult};
+//! use std::time::Duration;
+//!
+//! let mut collector = StatsCollector::new();
+//! collector.add(&PingResult::Pong(Duration::from_millis(20), PingReply::new(String::new())));
+//! collector.add(&PingResult::Timeout(String::new()));
+//! let stats = collector.stats();
+//! assert_eq!((stats.sent, stats.received, stats.lost), (2, 1, 1));
+//! assert_eq!(stats.loss, 50.0);
+//! ```
+use crate::PingResult;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PingStats {
+    pub sent: u64,
+    pub received: u64,
+    pub lost: u64,
+    pub duplicates: u64,
+    /// Replies that arrived after the reply to a later probe.
+    pub reordered: u64,
+    /// Percentage of probes that weren't answered.
+    pub loss: f64,
+    // The round trip times are None when nothing was received
+    pub min: Option<Duration>,
+    pub avg: Option<Duration>,
+    pub max: Option<Duration>,
+    /// Population standard deviation of the round trip times.
+    pub stddev: Option<Duration>,
+    /// Mean difference between consecutive round trip times.
+    pub jitter: Option<Duration>,
+    pub p50: Option<Duration>,
+    pub p90: Option<Duration>,
+    pub p95: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+/// Collects results as they arrive, to compute [`PingStats`] from at any point.
+#[derive(Debug, Clone, Default)]
+pub struct StatsCollector {
+    // In the order they were received
+    rtts: Vec<Duration>,
+    sent: u64,
+    duplicates: u64,
+    reordered: u64,
+}
+
+impl StatsCollector {
+    pub fn new() -> StatsCollector {
+        StatsCollector::default()
+    }
+
+    pub fn add(&mut self, result: &PingResult) {
+        if let PingResult::Pong(_, reply) = result {
+            if reply.reordered && !reply.duplicate {
+                self.reordered += 1;
+            }
+        }
+        match result {
+            PingResult::Pong(_, reply) if reply.duplicate => self.duplicates += 1,
+            // The probe was already counted when it timed out
+            PingResult::Pong(duration, reply) if reply.late => self.rtts.push(*duration),
+            PingResult::Pong(duration, _) => {
+                self.sent += 1;
+                self.rtts.push(*duration);
+            }
+            PingResult::Timeout(_)
+            | PingResult::Unreachable(_)
+            | PingResult::TtlExceeded(_)
+            | PingResult::HttpError(..)
+            | PingResult::DnsError(..) => self.sent += 1,
+            // Ping doesn't report a timeout for the last probe, but does count it here
+            PingResult::Summary(summary) => self.sent = self.sent.max(summary.transmitted),
+            _ => {}
+        }
+    }
+
+    pub fn stats(&self) -> PingStats {
+        let received = self.rtts.len() as u64;
+        let lost = self.sent.saturating_sub(received);
+        let mut stats = PingStats {
+            sent: self.sent,
+            received,
+            lost,
+            duplicates: self.duplicates,
+            reordered: self.reordered,
+            loss: if self.sent == 0 {
+                0f64
+            } else {
+                lost as f64 * 100f64 / self.sent as f64
+            },
+            ..PingStats::default()
+        };
+        if self.rtts.is_empty() {
+            return stats;
+        }
+
+        let secs: Vec<f64> = self.rtts.iter().map(Duration::as_secs_f64).collect();
+        let mean = secs.iter().sum::<f64>() / secs.len() as f64;
+        let variance = secs.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / secs.len() as f64;
+        stats.avg = Some(Duration::from_secs_f64(mean));
+        stats.stddev = Some(Duration::from_secs_f64(variance.sqrt()));
+        if secs.len() > 1 {
+            let jitter =
+                secs.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (secs.len() - 1) as f64;
+            stats.jitter = Some(Duration::from_secs_f64(jitter));
+        }
+
+        let mut sorted = self.rtts.clone();
+        sorted.sort();
+        stats.min = sorted.first().copied();
+        stats.max = sorted.last().copied();
+        stats.p50 = percentile(&sorted, 50f64);
+        stats.p90 = percentile(&sorted, 90f64);
+        stats.p95 = percentile(&sorted, 95f64);
+        stats.p99 = percentile(&sorted, 99f64);
+        stats
+    }
+}
+
+/// The nearest-rank percentile of sorted durations.
+pub fn percentile(sorted: &[Duration], percentile: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percentile / 100f64 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.clamp(1, sorted.len()) - 1).copied()
+}
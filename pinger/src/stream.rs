@@ -0,0 +1,449 @@
+//! An async counterpart to [`crate::ping_with_options`], available with the `async` feature.
+//!
+//! `ping` processes are driven with tokio rather than a dedicated thread, as are the ICMP sockets
+//! of the native backend, and both are closed as soon as the returned stream is dropped. If `ping`
+//! fails, the stream ends with a [`PingResult::Error`] holding what it wrote to stderr.
+//! ```no_run
+//! use pinger::{ping_stream, PingOptions};
+//! # async fn run() -> anyhow::Result<()> {
+//! use futures::StreamExt;
+//!
+//! let mut stream = ping_stream("tomforb.es".to_string(), PingOptions::default()).await?;
+//! while let Some(message) = stream.next().await {
+//!     println!("{}", message);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+#[cfg(target_os = "linux")]
+use crate::tracker::Outstanding;
+use crate::tracker::SeqTracker;
+use crate::{
+    bsd, clamp_interval, command, linux, macos, resolve_custom, Backend, Parser, PingError,
+    PingOptions, PingResult, Pinger, TRACKER_TICK,
+};
+#[cfg(target_os = "linux")]
+use crate::{check_native, ProbeSocket};
+#[cfg(windows)]
+use crate::{start_native, windows, PingHandle};
+use anyhow::Result;
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(windows)]
+use std::sync::mpsc::RecvTimeoutError;
+#[cfg(windows)]
+use std::sync::Arc;
+use std::task::{Context, Poll};
+#[cfg(windows)]
+use std::thread;
+#[cfg(any(windows, target_os = "linux"))]
+use std::time::Duration;
+use std::time::Instant;
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStderr, ChildStdout, Command};
+#[cfg(windows)]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, Interval};
+#[cfg(target_os = "linux")]
+use tokio::time::{sleep, Sleep};
+
+type BoxedStream = Pin<Box<dyn Stream<Item = PingResult> + Send>>;
+// How often a bridged handle checks whether its stream was dropped
+#[cfg(windows)]
+const BRIDGE_TICK: Duration = Duration::from_millis(100);
+
+type ExitFuture = Pin<Box<dyn Future<Output = io::Result<ExitStatus>> + Send>>;
+
+/// Start pinging an address, returning a stream of results. This must be called from within a
+/// tokio runtime.
+///
+/// Picking a backend may run `ping` to ask for its version, which is done on tokio's blocking
+/// thread pool, as is resolving the address for the native backend.
+pub async fn ping_stream(
+    addr: String,
+    options: PingOptions,
+) -> Result<impl Stream<Item = PingResult> + Send> {
+    let (backend, program, mut options) = spawn_blocking(move || -> Result<_> {
+        let (backend, program) = resolve_custom(Backend::detect(&options)?, &options);
+        Ok((backend, program, options))
+    })
+    .await??;
+    let pending: VecDeque<PingResult> = clamp_interval(&backend, &mut options)
+        .map(PingResult::Warning)
+        .into_iter()
+        .collect();
+    let stream = match backend {
+        #[cfg(windows)]
+        Backend::Windows => {
+            let handle = start_native::<_, windows::WindowsParser>(
+                &backend,
+                windows::WindowsPinger::default(),
+                addr,
+                options,
+            )?;
+            bridge(handle, pending)
+        }
+        #[cfg(target_os = "linux")]
+        Backend::NativeSocket => {
+            check_native(&backend, &options)?;
+            let mut pinger = linux::IcmpSocketPinger::default();
+            pinger.set_options(options.clone());
+            let (probes, timeout) = spawn_blocking(move || pinger.probes(&addr)).await??;
+            probes.set_nonblocking()?;
+            from_socket(options, timeout, probes, pending)?
+        }
+        Backend::Iputils => spawn::<_, linux::LinuxParser>(
+            linux::LinuxPinger::default(),
+            program,
+            addr,
+            options,
+            pending,
+        )?,
+        Backend::Busybox => spawn::<_, linux::LinuxParser>(
+            linux::AlpinePinger::default(),
+            program,
+            addr,
+            options,
+            pending,
+        )?,
+        Backend::Inetutils => spawn::<_, linux::LinuxParser>(
+            linux::InetutilsPinger::default(),
+            program,
+            addr,
+            options,
+            pending,
+        )?,
+        Backend::MacOS => spawn::<_, macos::MacOSParser>(
+            macos::MacOSPinger::default(),
+            program,
+            addr,
+            options,
+            pending,
+        )?,
+        Backend::Bsd(variant) => spawn::<_, bsd::BsdParser>(
+            bsd::BsdPinger::new(variant),
+            program,
+            addr,
+            options,
+            pending,
+        )?,
+        backend => return Err(PingError::UnsupportedBackend(backend.to_string()).into()),
+    };
+    Ok(stream)
+}
+
+fn spawn<T, P>(
+    pinger: T,
+    program: Option<String>,
+    addr: String,
+    options: PingOptions,
+    pending: VecDeque<PingResult>,
+) -> Result<BoxedStream>
+where
+    T: Pinger,
+    P: Parser + Send + Unpin + 'static,
+{
+    let pinger = command(pinger, program, options);
+    let program = pinger.program().to_string();
+    let mut command = Command::new(&program);
+    command
+        .args(pinger.ping_args(addr))
+        // Required to ensure that the output is formatted in the way we expect, not
+        // using locale specific delimiters.
+        .env("LANG", "C")
+        .env("LC_ALL", "C");
+    let tracker = pinger.seq_tracker().unwrap_or_else(SeqTracker::reconciling);
+    from_command::<P>(program, command, tracker, pending)
+}
+
+// Runs the command, with `pending` results coming out before any from its output
+pub(crate) fn from_command<P>(
+    program: String,
+    mut command: Command,
+    tracker: SeqTracker,
+    pending: VecDeque<PingResult>,
+) -> Result<BoxedStream>
+where
+    P: Parser + Send + Unpin + 'static,
+{
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| PingError::SpawnFailed(program, e))?;
+    let stdout = child.stdout.take().expect("child did not have a stdout");
+    let stderr = child.stderr.take().expect("child did not have a stderr");
+    // Without timeouts from ping itself, check regularly for replies that are overdue
+    let ticks = tracker
+        .synthesises_timeouts()
+        .then(|| interval(TRACKER_TICK));
+    Ok(Box::pin(ProcessStream {
+        child: Some(child),
+        exit: None,
+        lines: Some(BufReader::new(stdout).lines()),
+        stderr: Some(BufReader::new(stderr).lines()),
+        errors: VecDeque::new(),
+        parser: P::default(),
+        tracker,
+        ticks,
+        pending,
+    }))
+}
+
+// Sends probes on the socket, which must be non-blocking, and reads their replies as they arrive
+#[cfg(target_os = "linux")]
+pub(crate) fn from_socket<S>(
+    options: PingOptions,
+    timeout: Duration,
+    socket: S,
+    pending: VecDeque<PingResult>,
+) -> Result<BoxedStream>
+where
+    S: ProbeSocket + AsRawFd + Unpin,
+{
+    Ok(Box::pin(SocketStream {
+        socket: AsyncFd::new(socket)?,
+        outstanding: Outstanding::new(),
+        timeout,
+        // tokio's intervals can't be zero
+        ticks: interval(options.interval.max(Duration::from_millis(1))),
+        expiry: Box::pin(sleep(timeout)),
+        started: Instant::now(),
+        count: options.count.map(u64::from),
+        deadline: options.deadline,
+        seq: 0,
+        pending,
+    }))
+}
+
+// Forwards results from a handle until the stream is dropped, which stops the handle within a
+// tick rather than when the next result arrives. Only the Windows backend, which pings with
+// blocking calls, needs this.
+#[cfg(windows)]
+pub(crate) fn bridge(handle: PingHandle, pending: VecDeque<PingResult>) -> BoxedStream {
+    let (tx, rx) = unbounded_channel();
+    for result in pending {
+        let _ = tx.send(result);
+    }
+    let dropped = Arc::new(AtomicBool::new(false));
+    let stopped = Arc::clone(&dropped);
+    thread::spawn(move || {
+        while !stopped.load(Ordering::Acquire) {
+            match handle.recv_timeout(BRIDGE_TICK) {
+                Ok(result) => {
+                    if tx.send(result).is_err() {
+                        break;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+    });
+    Box::pin(ChannelStream { rx, dropped })
+}
+
+struct ProcessStream<P> {
+    // Kept, and then waited on once its output ends, so that the process is killed when the
+    // stream is dropped
+    child: Option<Child>,
+    exit: Option<ExitFuture>,
+    // Each is `None` once it's closed
+    lines: Option<Lines<BufReader<ChildStdout>>>,
+    stderr: Option<Lines<BufReader<ChildStderr>>>,
+    // The last few lines of stderr, which are all we need to explain why ping exited
+    errors: VecDeque<String>,
+    parser: P,
+    tracker: SeqTracker,
+    // Only while the tracker synthesises timeouts
+    ticks: Option<Interval>,
+    pending: VecDeque<PingResult>,
+}
+
+impl<P> ProcessStream<P> {
+    // Read stderr as it's written, so that a chatty ping can't block on a full pipe
+    fn read_stderr(&mut self, cx: &mut Context<'_>) {
+        while let Some(stderr) = &mut self.stderr {
+            match Pin::new(stderr).poll_next_line(cx) {
+                Poll::Ready(Ok(Some(line))) => {
+                    if self.errors.len() == 10 {
+                        self.errors.pop_front();
+                    }
+                    self.errors.push_back(line);
+                }
+                Poll::Ready(_) => self.stderr = None,
+                Poll::Pending => return,
+            }
+        }
+    }
+}
+
+impl<P: Parser + Unpin> Stream for ProcessStream<P> {
+    type Item = PingResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PingResult>> {
+        let this = &mut *self;
+        loop {
+            if let Some(result) = this.pending.pop_front() {
+                return Poll::Ready(Some(result));
+            }
+            this.read_stderr(cx);
+            if let Some(ticks) = &mut this.ticks {
+                if ticks.poll_tick(cx).is_ready() {
+                    let overdue = this.tracker.tick(Instant::now());
+                    this.pending.extend(overdue);
+                    continue;
+                }
+            }
+            let lines = match &mut this.lines {
+                Some(lines) => lines,
+                None => break,
+            };
+            match Pin::new(lines).poll_next_line(cx) {
+                Poll::Ready(Ok(Some(line))) => {
+                    if let Some(result) = this.parser.parse(line) {
+                        let tracked = this.tracker.track(result, Instant::now());
+                        this.pending.extend(tracked);
+                    }
+                }
+                Poll::Ready(_) => {
+                    this.lines = None;
+                    this.tracker.finish();
+                    this.ticks = None;
+                    this.pending.extend(this.parser.finish());
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // Once the output has ended, report why ping exited if it failed, as Pinger::start does
+        if this.stderr.is_some() {
+            return Poll::Pending;
+        }
+        if let Some(mut child) = this.child.take() {
+            this.exit = Some(Box::pin(async move { child.wait().await }));
+        }
+        let exit = match &mut this.exit {
+            Some(exit) => exit,
+            None => return Poll::Ready(None),
+        };
+        let status = match exit.as_mut().poll(cx) {
+            Poll::Ready(status) => status.ok(),
+            Poll::Pending => return Poll::Pending,
+        };
+        this.exit = None;
+        if status.is_some_and(|status| status.success()) {
+            return Poll::Ready(None);
+        }
+        let stderr = Vec::from(std::mem::take(&mut this.errors)).join("\n");
+        Poll::Ready(Some(PingResult::Error(stderr, status)))
+    }
+}
+
+// The async side of `probe_outstanding`: a probe goes out every tick whether or not the last one
+// was answered, until the count or deadline runs out and nothing is outstanding.
+#[cfg(target_os = "linux")]
+struct SocketStream<S: AsRawFd> {
+    socket: AsyncFd<S>,
+    outstanding: Outstanding,
+    timeout: Duration,
+    ticks: Interval,
+    // When the oldest outstanding probe times out
+    expiry: Pin<Box<Sleep>>,
+    started: Instant,
+    count: Option<u64>,
+    deadline: Option<Duration>,
+    seq: u64,
+    pending: VecDeque<PingResult>,
+}
+
+#[cfg(target_os = "linux")]
+impl<S: ProbeSocket + AsRawFd + Unpin> Stream for SocketStream<S> {
+    type Item = PingResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PingResult>> {
+        let this = &mut *self;
+        loop {
+            if let Some(result) = this.pending.pop_front() {
+                return Poll::Ready(Some(result));
+            }
+            let now = Instant::now();
+            let sending = this.count.is_none_or(|count| this.seq < count)
+                && this
+                    .deadline
+                    .is_none_or(|limit| now.duration_since(this.started) < limit);
+            if !sending && this.outstanding.is_empty() {
+                return Poll::Ready(None);
+            }
+            if sending && this.ticks.poll_tick(cx).is_ready() {
+                this.seq += 1;
+                this.socket.get_mut().send(this.seq);
+                this.outstanding.sent(this.seq, Instant::now());
+                continue;
+            }
+
+            for seq in this.outstanding.expire(Instant::now() - this.timeout) {
+                let timed_out = this.socket.get_ref().timed_out(seq);
+                this.pending.push_back(timed_out);
+            }
+            if !this.pending.is_empty() {
+                continue;
+            }
+            if let Some(oldest) = this.outstanding.oldest() {
+                let expires = tokio::time::Instant::from_std(oldest + this.timeout);
+                this.expiry.as_mut().reset(expires);
+                if this.expiry.as_mut().poll(cx).is_ready() {
+                    continue;
+                }
+            }
+
+            let mut ready = match this.socket.poll_read_ready_mut(cx) {
+                Poll::Ready(Ok(ready)) => ready,
+                // The socket can't be read at all, so there won't be any more replies
+                Poll::Ready(Err(_)) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            let outstanding = &mut this.outstanding;
+            // Reading until it would block clears the readiness, so that we're woken again
+            if let Ok(Ok(Some(result))) =
+                ready.try_io(|socket| socket.get_mut().receive(outstanding))
+            {
+                this.pending.push_back(result);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+struct ChannelStream {
+    rx: UnboundedReceiver<PingResult>,
+    dropped: Arc<AtomicBool>,
+}
+
+#[cfg(windows)]
+impl Stream for ChannelStream {
+    type Item = PingResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PingResult>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ChannelStream {
+    fn drop(&mut self) {
+        self.dropped.store(true, Ordering::Release);
+    }
+}
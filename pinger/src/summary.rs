@@ -0,0 +1,126 @@
+//! The totals `ping` prints when it exits, e.g.
+//! ```text
+//! 10 packets transmitted, 9 received, 10% packet loss, time 9013ms
+//! rtt min/avg/max/mdev = 13.930/24.707/50.513/11.383 ms
+//! ```
+use crate::PingResult;
+use regex::Regex;
+use std::fmt;
+use std::fmt::Formatter;
+use std::time::Duration;
+
+lazy_static! {
+    // iputils and busybox, as well as macOS and the BSDs which also say "packets received"
+    static ref TOTALS_RE: Regex = Regex::new(
+        r"^(?P<transmitted>\d+) packets transmitted, (?P<received>\d+)(?: packets)? received,(?: \+(?P<duplicates>\d+) duplicates,)?(?: \+(?P<errors>\d+) errors,)? (?P<loss>\d+(?:\.\d+)?)% packet loss(?:, time (?P<time>\d+)ms)?"
+    )
+    .unwrap();
+    // Busybox leaves out the deviation, and it's called stddev or std-dev on macOS and the BSDs
+    static ref RTT_RE: Regex = Regex::new(
+        r"^(?:rtt|round-trip) min/avg/max(?:/(?:mdev|stddev|std-dev))? = (?P<min>\d+(?:\.\d+)?)/(?P<avg>\d+(?:\.\d+)?)/(?P<max>\d+(?:\.\d+)?)(?:/(?P<stddev>\d+(?:\.\d+)?))? ms"
+    )
+    .unwrap();
+}
+
+/// The totals reported by `ping` itself once it's finished.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PingSummary {
+    pub transmitted: u64,
+    pub received: u64,
+    pub duplicates: Option<u64>,
+    /// ICMP errors received instead of replies, as counted by iputils.
+    pub errors: Option<u64>,
+    /// Percentage of probes that weren't answered.
+    pub loss: f64,
+    /// How long ping ran for, if reported.
+    pub time: Option<Duration>,
+    /// Round trip times, which are left out when nothing was received.
+    pub rtt: Option<RttSummary>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RttSummary {
+    pub min: Duration,
+    pub avg: Duration,
+    pub max: Duration,
+    /// The standard deviation (iputils calls it mdev), if reported.
+    pub stddev: Option<Duration>,
+}
+
+impl fmt::Display for PingSummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} transmitted, {} received",
+            self.transmitted, self.received
+        )?;
+        if let Some(duplicates) = self.duplicates {
+            write!(f, ", +{} duplicates", duplicates)?;
+        }
+        if let Some(errors) = self.errors {
+            write!(f, ", +{} errors", errors)?;
+        }
+        write!(f, ", {}% packet loss", self.loss)?;
+        if let Some(time) = self.time {
+            write!(f, ", time {:?}", time)?;
+        }
+        if let Some(rtt) = &self.rtt {
+            write!(f, ", rtt {:?}/{:?}/{:?}", rtt.min, rtt.avg, rtt.max)?;
+            if let Some(stddev) = rtt.stddev {
+                write!(f, "/{:?}", stddev)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the summary, which is spread over two lines. The round trip times are left out when
+/// nothing was received, so the totals alone are only passed on by [`SummaryParser::finish`].
+#[derive(Default)]
+pub(crate) struct SummaryParser {
+    pending: Option<PingSummary>,
+}
+
+impl SummaryParser {
+    /// Returns `None` if the line isn't part of the summary. Otherwise the summary is returned
+    /// once it's complete.
+    pub fn parse(&mut self, line: &str) -> Option<Option<PingResult>> {
+        if let Some(cap) = TOTALS_RE.captures(line) {
+            self.pending = Some(PingSummary {
+                transmitted: cap["transmitted"].parse().ok()?,
+                received: cap["received"].parse().ok()?,
+                duplicates: cap.name("duplicates").and_then(|m| m.as_str().parse().ok()),
+                errors: cap.name("errors").and_then(|m| m.as_str().parse().ok()),
+                loss: cap["loss"].parse().ok()?,
+                time: cap
+                    .name("time")
+                    .and_then(|m| m.as_str().parse().ok())
+                    .map(Duration::from_millis),
+                rtt: None,
+            });
+            return Some(None);
+        }
+        let cap = RTT_RE.captures(line)?;
+        let rtt = RttSummary {
+            min: millis(&cap["min"])?,
+            avg: millis(&cap["avg"])?,
+            max: millis(&cap["max"])?,
+            stddev: cap.name("stddev").and_then(|m| millis(m.as_str())),
+        };
+        let summary = self.pending.take().map(|summary| PingSummary {
+            rtt: Some(rtt),
+            ..summary
+        });
+        Some(summary.map(PingResult::Summary))
+    }
+
+    /// Passes on the totals if they were never followed by round trip times.
+    pub fn finish(&mut self) -> Option<PingResult> {
+        self.pending.take().map(PingResult::Summary)
+    }
+}
+
+fn millis(value: &str) -> Option<Duration> {
+    let millis: f64 = value.parse().ok()?;
+    Some(Duration::from_micros((millis * 1000f64).round() as u64))
+}
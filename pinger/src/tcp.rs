@@ -0,0 +1,72 @@
+//! Measures latency with TCP handshakes rather than ICMP, for hosts that drop pings.
+//!
+//! The target is a `host:port`. A refused connection still means the host answered, so it's
+//! reported as a pong with [`PingReply::closed`] set.
+use crate::{
+    probe_every_interval, resolve, Parser, PingHandle, PingOptions, PingReply, PingResult, Pinger,
+};
+use anyhow::Result;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+// How long to wait for a handshake without a timeout option
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct TcpPinger {
+    options: PingOptions,
+}
+
+impl Pinger for TcpPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let options = self.options.clone();
+        let addr = resolve(&target, None, options.ip_version)?;
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+
+        Ok(probe_every_interval(options.clone(), move |seq| {
+            let sent = Instant::now();
+            let result = TcpStream::connect_timeout(&addr, timeout);
+            let duration = sent.elapsed();
+            let reply = |status: &str| PingReply {
+                seq: Some(seq),
+                from: Some(addr.ip()),
+                ..PingReply::new(format!(
+                    "{} {} seq={} time={:.3} ms",
+                    status,
+                    addr,
+                    seq,
+                    duration.as_micros() as f64 / 1_000f64
+                ))
+            };
+            match result {
+                Ok(_) => PingResult::Pong(duration, reply("connected to")),
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => PingResult::Pong(
+                    duration,
+                    PingReply {
+                        closed: true,
+                        ..reply("connection refused by")
+                    },
+                ),
+                Err(e) if e.kind() == ErrorKind::TimedOut => {
+                    PingResult::Timeout(format!("no answer yet for {} seq={}", addr, seq))
+                }
+                Err(e) => PingResult::Unreachable(PingReply {
+                    from: None,
+                    ..reply(&format!("{}, connecting to", e))
+                }),
+            }
+        }))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
@@ -1,21 +1,77 @@
 #[cfg(test)]
 mod tests {
-    use crate::linux::LinuxParser;
+    use crate::backend::{privileged, Backend};
+    use crate::bsd::{BsdParser, BsdPinger, BsdVariant};
+    use crate::dns::{DnsPinger, RecordType};
+    use crate::http::{HttpPinger, Url};
+    use crate::linux::{AlpinePinger, InetutilsPinger, LinuxParser, LinuxPinger};
     // use crate::alpine::AlpineParser;
-    use crate::macos::MacOSParser;
+    use crate::macos::{MacOSParser, MacOSPinger};
+    use crate::replay::ReplayPinger;
+    use crate::route::{parse_ipv4_routes, parse_ipv6_routes, DefaultRoute};
+    use crate::stats::{percentile, PingStats, StatsCollector};
+    use crate::tcp::TcpPinger;
+    #[cfg(target_os = "linux")]
+    use crate::trace::{Method, Tracer};
+    use crate::traceroute::{parse, Annotation, MplsLabel};
+    use crate::tracker::{result_seq, Outstanding, SeqTracker, DEFAULT_GRACE};
+    use crate::udp::{Probe, UdpPinger};
     #[cfg(windows)]
     use crate::windows::WindowsParser;
-    use crate::{Parser, PingResult};
+    use crate::{
+        clamp_interval, CustomCommand, IpVersion, Parser, PingError, PingHandle, PingOptions,
+        PingReply, PingResult, PingSummary, Pinger,
+    };
+    #[cfg(target_os = "linux")]
+    use crate::{icmp, linux::IcmpSocketPinger};
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{mpsc, Arc};
+    use std::time::{Duration, Instant};
+
+    // Formats a result along with any reply details the parser extracted
+    fn describe(result: &PingResult) -> String {
+        let (mut description, reply) = match result {
+            PingResult::Pong(duration, reply) => (format!("{:?}", duration), reply),
+            PingResult::Unreachable(reply)
+            | PingResult::TtlExceeded(reply)
+            | PingResult::Redirect(reply) => (format!("{}", result), reply),
+            _ => return format!("{}", result),
+        };
+        if let Some(seq) = reply.seq {
+            description.push_str(&format!(" seq={}", seq));
+        }
+        if let Some(ttl) = reply.ttl {
+            description.push_str(&format!(" ttl={}", ttl));
+        }
+        if let Some(bytes) = reply.bytes {
+            description.push_str(&format!(" bytes={}", bytes));
+        }
+        if let Some(from) = reply.from {
+            description.push_str(&format!(" from={}", from));
+        }
+        if reply.duplicate {
+            description.push_str(" dup");
+        }
+        if reply.late {
+            description.push_str(" late");
+        }
+        description
+    }
 
     fn test_parser<T>(contents: &str)
     where
         T: Parser,
     {
-        let parser = T::default();
+        let mut parser = T::default();
         let test_file: Vec<&str> = contents.split("-----").collect();
         let input = test_file[0].trim().split("\n");
         let expected: Vec<&str> = test_file[1].trim().split("\n").collect();
-        let parsed: Vec<Option<PingResult>> = input.map(|l| parser.parse(l.to_string())).collect();
+        let mut parsed: Vec<Option<PingResult>> =
+            input.map(|l| parser.parse(l.to_string())).collect();
+        // Anything held back until the end is expected after the last line
+        if let Some(result) = parser.finish() {
+            parsed.push(Some(result));
+        }
 
         assert_eq!(
             parsed.len(),
@@ -27,12 +83,7 @@ mod tests {
 
         for (idx, (output, expected)) in parsed.into_iter().zip(expected).enumerate() {
             if let Some(value) = output {
-                assert_eq!(
-                    format!("{}", value).trim(),
-                    expected.trim(),
-                    "Failed at idx {}",
-                    idx
-                )
+                assert_eq!(describe(&value), expected.trim(), "Failed at idx {}", idx)
             } else {
                 assert_eq!("None", expected.trim(), "Failed at idx {}", idx)
             }
@@ -49,6 +100,11 @@ mod tests {
         test_parser::<LinuxParser>(include_str!("tests/ubuntu.txt"));
     }
 
+    #[test]
+    fn linux_size() {
+        test_parser::<LinuxParser>(include_str!("tests/linux_size.txt"));
+    }
+
     #[test]
     fn debian() {
         test_parser::<LinuxParser>(include_str!("tests/debian.txt"));
@@ -68,5 +124,1571 @@ mod tests {
     #[test]
     fn alpine() {
         test_parser::<LinuxParser>(include_str!("tests/alpine.txt"));
+        let tracker = SeqTracker::new(Duration::from_secs(1), DEFAULT_GRACE, Instant::now());
+        test_tracked_parser::<LinuxParser>(include_str!("tests/alpine.txt"), tracker);
+    }
+
+    #[test]
+    fn linux_summary() {
+        test_parser::<LinuxParser>(include_str!("tests/linux_summary.txt"));
+    }
+
+    #[test]
+    fn alpine_summary() {
+        test_parser::<LinuxParser>(include_str!("tests/alpine_summary.txt"));
+    }
+
+    #[test]
+    fn linux_dup_late() {
+        test_parser::<LinuxParser>(include_str!("tests/linux_dup_late.txt"));
+        test_tracked_parser::<LinuxParser>(
+            include_str!("tests/linux_dup_late.txt"),
+            SeqTracker::reconciling(),
+        );
+    }
+
+    // Checks the optional third section of a fixture, which is what we expect once the parsed
+    // results have been through a sequence tracker. Every line arrives at once, so only gaps
+    // can produce timeouts.
+    fn test_tracked_parser<T>(contents: &str, mut tracker: SeqTracker)
+    where
+        T: Parser,
+    {
+        let mut parser = T::default();
+        let test_file: Vec<&str> = contents.split("-----").collect();
+        let expected: Vec<&str> = test_file[2].trim().split("\n").collect();
+        let now = Instant::now();
+        let mut parsed: Vec<PingResult> = test_file[0]
+            .trim()
+            .split("\n")
+            .filter_map(|l| parser.parse(l.to_string()))
+            .collect();
+        parsed.extend(parser.finish());
+        let tracked: Vec<String> = parsed
+            .into_iter()
+            .flat_map(|result| tracker.track(result, now))
+            .map(|result| describe(&result))
+            .collect();
+        assert_eq!(tracked, expected);
+    }
+
+    #[test]
+    fn tracker_deadline() {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        let mut tracker = SeqTracker::new(second, second, start).first_seq(1).count(3);
+        assert!(tracker.tick(start + second).is_empty());
+        // Seq 1 is due by 2s, seq 2 by 3s
+        let timeouts = tracker.tick(start + second * 3);
+        assert_eq!(timeouts.len(), 2);
+        assert!(matches!(&timeouts[1], PingResult::Timeout(line) if line.ends_with("icmp_seq=2")));
+        // A reply for seq 2 arriving after its timeout is passed on alone
+        let late = PingResult::Pong(
+            Duration::from_millis(2500),
+            PingReply {
+                seq: Some(2),
+                ..PingReply::new(String::new())
+            },
+        );
+        assert_eq!(tracker.track(late, start + second * 3).len(), 1);
+        // Only three requests are sent
+        assert_eq!(tracker.tick(start + second * 10).len(), 1);
+        assert!(tracker.tick(start + second * 20).is_empty());
+    }
+
+    #[test]
+    fn tracker_wraps_seq() {
+        let start = Instant::now();
+        let mut tracker =
+            SeqTracker::new(Duration::from_secs(1), DEFAULT_GRACE, start).first_seq(65534);
+        let pong = |seq| {
+            PingResult::Pong(
+                Duration::from_millis(10),
+                PingReply {
+                    seq: Some(seq),
+                    ..PingReply::new(String::new())
+                },
+            )
+        };
+        assert_eq!(tracker.track(pong(65535), start).len(), 2);
+        assert_eq!(tracker.track(pong(1), start).len(), 2);
+    }
+
+    #[test]
+    fn linux_icmp_errors() {
+        test_parser::<LinuxParser>(include_str!("tests/linux_icmp_errors.txt"));
+    }
+
+    #[test]
+    fn macos_icmp_errors() {
+        test_parser::<MacOSParser>(include_str!("tests/macos_icmp_errors.txt"));
+    }
+
+    #[test]
+    fn freebsd() {
+        test_parser::<BsdParser>(include_str!("tests/freebsd.txt"));
+    }
+
+    #[test]
+    fn openbsd() {
+        test_parser::<BsdParser>(include_str!("tests/openbsd.txt"));
+        let tracker = SeqTracker::new(Duration::from_secs(1), DEFAULT_GRACE, Instant::now());
+        test_tracked_parser::<BsdParser>(include_str!("tests/openbsd.txt"), tracker);
+    }
+
+    fn ping_args<T: Pinger>(options: PingOptions) -> Vec<String> {
+        let mut pinger = T::default();
+        pinger.set_options(options);
+        pinger.ping_args("example.com".to_string())
+    }
+
+    fn all_options() -> PingOptions {
+        PingOptions::new()
+            .interval(Duration::from_millis(500))
+            .count(5)
+            .size(100)
+            .ttl(32)
+            .timeout(Duration::from_millis(1500))
+            .deadline(Duration::from_millis(9500))
+            .interface("eth0")
+            .dont_fragment(true)
+            .ip_version(IpVersion::V4)
+    }
+
+    #[test]
+    fn linux_args() {
+        assert_eq!(
+            ping_args::<LinuxPinger>(PingOptions::new()),
+            vec!["-O", "-i0.2", "example.com"]
+        );
+        assert_eq!(
+            ping_args::<LinuxPinger>(all_options()),
+            vec![
+                "-O",
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-t32",
+                "-W2",
+                "-w10",
+                "-Ieth0",
+                "-Mdo",
+                "-4",
+                "example.com"
+            ]
+        );
+        assert_eq!(
+            ping_args::<LinuxPinger>(PingOptions::new().source("10.0.0.2".parse().unwrap())),
+            vec!["-O", "-i0.2", "-I10.0.0.2", "example.com"]
+        );
+    }
+
+    #[test]
+    fn interval_precision() {
+        let options = PingOptions::new().interval(Duration::from_millis(250));
+        assert_eq!(
+            ping_args::<LinuxPinger>(options.clone()),
+            vec!["-O", "-i0.25", "example.com"]
+        );
+        assert_eq!(
+            ping_args::<AlpinePinger>(options.clone()),
+            vec!["-i0.25", "example.com"]
+        );
+        assert_eq!(
+            ping_args::<MacOSPinger>(options.interval(Duration::from_millis(50))),
+            vec!["-i0.05", "example.com"]
+        );
+    }
+
+    #[test]
+    fn interval_clamping() {
+        let mut options = PingOptions::new().interval(Duration::from_millis(50));
+        let warning = clamp_interval(&Backend::Iputils, &mut options);
+        if privileged() {
+            assert!(warning.is_none());
+            assert_eq!(options.interval, Duration::from_millis(50));
+        } else {
+            assert_eq!(
+                warning.as_deref(),
+                Some("Raised the interval from 0.05s to 0.2s, as only root can ping more often")
+            );
+            assert_eq!(options.interval, Duration::from_millis(200));
+        }
+
+        // Busybox and our own sockets don't mind, and neither do long enough intervals
+        for backend in [Backend::Busybox, Backend::NativeSocket] {
+            let mut options = PingOptions::new().interval(Duration::from_millis(10));
+            assert!(clamp_interval(&backend, &mut options).is_none());
+        }
+        let mut options = PingOptions::new().interval(Duration::from_secs(1));
+        assert!(clamp_interval(&Backend::Bsd(BsdVariant::OpenBSD), &mut options).is_none());
+
+        let options = PingOptions::new()
+            .interval(Duration::from_millis(50))
+            .backend(Backend::Iputils);
+        let expected = if privileged() { 50 } else { 200 };
+        assert_eq!(
+            crate::effective_interval(&options),
+            Duration::from_millis(expected)
+        );
+    }
+
+    #[test]
+    fn handle_pending() {
+        let (tx, rx) = mpsc::channel();
+        let handle = PingHandle::new(rx, Arc::new(AtomicBool::new(false)))
+            .with_pending(PingResult::Warning("slower".to_string()));
+        tx.send(PingResult::Timeout(String::new())).unwrap();
+        drop(tx);
+        let results: Vec<PingResult> = handle.collect();
+        assert!(matches!(results[0], PingResult::Warning(_)));
+        assert!(matches!(results[1], PingResult::Timeout(_)));
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn alpine_args() {
+        assert_eq!(
+            ping_args::<AlpinePinger>(PingOptions::new()),
+            vec!["-i0.2", "example.com"]
+        );
+        assert_eq!(
+            ping_args::<AlpinePinger>(all_options()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-t32",
+                "-W2",
+                "-w10",
+                "-Ieth0",
+                "-4",
+                "example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn inetutils_args() {
+        assert_eq!(
+            ping_args::<InetutilsPinger>(all_options()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "--ttl=32",
+                "-W2",
+                "-w10",
+                "example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn macos_args() {
+        assert_eq!(
+            ping_args::<MacOSPinger>(PingOptions::new()),
+            vec!["-i0.2", "example.com"]
+        );
+        assert_eq!(
+            ping_args::<MacOSPinger>(all_options().source("10.0.0.2".parse().unwrap())),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-m32",
+                "-W1500",
+                "-t10",
+                "-beth0",
+                "-D",
+                "-S10.0.0.2",
+                "example.com"
+            ]
+        );
+        let mut pinger = MacOSPinger::default();
+        pinger.set_options(all_options().ip_version(IpVersion::V6));
+        assert_eq!(pinger.program(), "ping6");
+        assert_eq!(
+            pinger.ping_args("example.com".to_string()),
+            vec!["-i0.5", "-c5", "-s100", "-h32", "-Ieth0", "example.com"]
+        );
+    }
+
+    #[test]
+    fn bsd_args() {
+        let bsd_args = |variant, options| {
+            let mut pinger = BsdPinger::new(variant);
+            pinger.set_options(options);
+            pinger.ping_args("example.com".to_string())
+        };
+        let options = || all_options().source("10.0.0.2".parse().unwrap());
+        assert_eq!(
+            bsd_args(BsdVariant::FreeBSD, PingOptions::new()),
+            vec!["-i0.2", "example.com"]
+        );
+        assert_eq!(
+            bsd_args(BsdVariant::FreeBSD, options()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-m32",
+                "-W1500",
+                "-t10",
+                "-D",
+                "-S10.0.0.2",
+                "-4",
+                "example.com"
+            ]
+        );
+        assert_eq!(
+            bsd_args(BsdVariant::OpenBSD, options()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-t32",
+                "-w2",
+                "-D",
+                "-I10.0.0.2",
+                "-4",
+                "example.com"
+            ]
+        );
+        assert_eq!(
+            bsd_args(BsdVariant::NetBSD, options()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-T32",
+                "-w10",
+                "-D",
+                "-I10.0.0.2",
+                "example.com"
+            ]
+        );
+        let mut pinger = BsdPinger::new(BsdVariant::NetBSD);
+        pinger.set_options(options().ip_version(IpVersion::V6));
+        assert_eq!(pinger.program(), "ping6");
+        assert_eq!(
+            pinger.ping_args("example.com".to_string()),
+            vec![
+                "-i0.5",
+                "-c5",
+                "-s100",
+                "-h32",
+                "-w10",
+                "-S10.0.0.2",
+                "example.com"
+            ]
+        );
+    }
+
+    // Checks the default routes found in a table against the ones after its "-----" line
+    fn test_routes(contents: &str, parse: fn(&str) -> Vec<DefaultRoute>) {
+        let (table, expected) = contents.split_once("-----").unwrap();
+        let routes: Vec<String> = parse(table.trim())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let expected: Vec<&str> = expected
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        assert_eq!(routes, expected);
+    }
+
+    // The fixtures were captured on a little endian machine
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn ipv4_routes() {
+        test_routes(include_str!("tests/route.txt"), parse_ipv4_routes);
+        test_routes(include_str!("tests/route_vpn.txt"), parse_ipv4_routes);
+        test_routes(include_str!("tests/route_none.txt"), parse_ipv4_routes);
+    }
+
+    #[test]
+    fn ipv6_routes() {
+        test_routes(include_str!("tests/ipv6_route.txt"), parse_ipv6_routes);
+    }
+
+    // Checks the hops parsed from a trace against the ones after its "-----" line
+    fn test_traceroute(contents: &str) {
+        let (output, expected) = contents.split_once("-----").unwrap();
+        let hops: Vec<String> = parse(output).iter().map(ToString::to_string).collect();
+        let expected: Vec<&str> = expected
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        assert_eq!(hops, expected);
+    }
+
+    #[test]
+    fn traceroute_output() {
+        test_traceroute(include_str!("tests/linux_traceroute.txt"));
+        test_traceroute(include_str!("tests/busybox_traceroute.txt"));
+        test_traceroute(include_str!("tests/linux_tracepath.txt"));
+        test_traceroute(include_str!("tests/macos_traceroute.txt"));
+        test_traceroute(include_str!("tests/windows_tracert.txt"));
+    }
+
+    #[test]
+    fn traceroute_hops() {
+        let hops = parse(include_str!("tests/linux_traceroute.txt"));
+        assert_eq!(
+            hops[3].responders(),
+            [
+                "203.0.113.1".parse::<std::net::IpAddr>().unwrap(),
+                "203.0.113.2".parse().unwrap()
+            ]
+        );
+        assert!(!hops[2].responded());
+        assert_eq!(
+            hops[6].probes[2].annotation,
+            Some(Annotation::FragmentationNeeded(Some(1400)))
+        );
+        assert_eq!(
+            hops[5].probes[0].mpls,
+            [
+                MplsLabel {
+                    label: 16004,
+                    exp: 0,
+                    bottom: false,
+                    ttl: 1
+                },
+                MplsLabel {
+                    label: 299792,
+                    exp: 0,
+                    bottom: true,
+                    ttl: 1
+                }
+            ]
+        );
+        assert_eq!(hops[0].probes[0].rtt, Some(Duration::from_micros(512)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn native_custom_command() {
+        for options in [
+            PingOptions::new().program("ping6"),
+            PingOptions::new().prefix(["sudo"]),
+            PingOptions::new().extra_args(["-n"]),
+        ] {
+            let error = crate::ping_with_options(
+                "127.0.0.1".to_string(),
+                options.backend(Backend::NativeSocket),
+            )
+            .err()
+            .unwrap();
+            assert!(matches!(
+                error.downcast_ref::<PingError>(),
+                Some(PingError::UnsupportedBackend(..))
+            ));
+        }
+    }
+
+    #[test]
+    fn backend_names() {
+        for name in [
+            "iputils",
+            "busybox",
+            "openbsd",
+            "native",
+            "custom:/usr/bin/ping6",
+        ] {
+            assert_eq!(name.parse::<Backend>().unwrap().to_string(), name);
+        }
+        assert!(matches!(
+            "custom:".parse::<Backend>(),
+            Err(PingError::UnsupportedBackend(..))
+        ));
+        assert_eq!(
+            Backend::detect(&PingOptions::new().backend(Backend::Busybox)).unwrap(),
+            Backend::Busybox
+        );
+    }
+
+    #[test]
+    fn backend_identify() {
+        let outputs = [
+            ("ping from iputils 20211215", Some(Backend::Iputils)),
+            ("ping utility, iputils-s20161105", Some(Backend::Iputils)),
+            (
+                "ping: unrecognized option: V\nBusyBox v1.36.1 (2023-07-27 17:12:24 UTC) multi-call binary.",
+                Some(Backend::Busybox),
+            ),
+            ("ping (GNU inetutils) 2.4", Some(Backend::Inetutils)),
+            ("ping: illegal option -- V", None),
+        ];
+        for (output, expected) in outputs {
+            assert_eq!(Backend::identify(output), expected, "{}", output);
+        }
+    }
+
+    fn pong(millis: u64) -> PingResult {
+        PingResult::Pong(Duration::from_millis(millis), PingReply::new(String::new()))
+    }
+
+    #[test]
+    fn stats_empty() {
+        let stats = StatsCollector::new().stats();
+        assert_eq!(stats, PingStats::default());
+        let mut collector = StatsCollector::new();
+        collector.add(&PingResult::Timeout(String::new()));
+        let stats = collector.stats();
+        assert_eq!((stats.sent, stats.lost, stats.loss), (1, 1, 100f64));
+        assert_eq!((stats.min, stats.avg, stats.jitter), (None, None, None));
+    }
+
+    #[test]
+    fn stats_sequence() {
+        let mut collector = StatsCollector::new();
+        for millis in [10, 30, 20, 40] {
+            collector.add(&pong(millis));
+        }
+        collector.add(&PingResult::Timeout(String::new()));
+        let stats = collector.stats();
+        assert_eq!((stats.sent, stats.received, stats.lost), (5, 4, 1));
+        assert_eq!(stats.loss, 20f64);
+        assert_eq!(stats.min, Some(Duration::from_millis(10)));
+        assert_eq!(stats.max, Some(Duration::from_millis(40)));
+        assert_eq!(stats.avg, Some(Duration::from_millis(25)));
+        // sqrt((225 + 25 + 25 + 225) / 4) = 11.18ms
+        assert_eq!(stats.stddev.unwrap().as_micros(), 11180);
+        // (20 + 10 + 20) / 3
+        assert_eq!(stats.jitter.unwrap().as_micros(), 16666);
+        assert_eq!(stats.p50, Some(Duration::from_millis(20)));
+        assert_eq!(stats.p95, Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn stats_duplicate_late_and_summary() {
+        let mut collector = StatsCollector::new();
+        collector.add(&pong(10));
+        let mut duplicate = PingReply::new(String::new());
+        duplicate.duplicate = true;
+        collector.add(&PingResult::Pong(Duration::from_millis(11), duplicate));
+        collector.add(&PingResult::Timeout(String::new()));
+        let mut late = PingReply::new(String::new());
+        late.late = true;
+        collector.add(&PingResult::Pong(Duration::from_millis(1500), late));
+        let stats = collector.stats();
+        assert_eq!((stats.sent, stats.received, stats.duplicates), (2, 2, 1));
+        // The last probe was lost without a timeout being reported
+        collector.add(&PingResult::Summary(PingSummary {
+            transmitted: 3,
+            received: 2,
+            duplicates: Some(1),
+            errors: None,
+            loss: 33.3,
+            time: None,
+            rtt: None,
+        }));
+        assert_eq!(collector.stats().lost, 1);
+    }
+
+    #[test]
+    fn stats_percentiles() {
+        let sorted: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&sorted, 50f64), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&sorted, 99f64), Some(Duration::from_millis(99)));
+        assert_eq!(
+            percentile(&sorted, 100f64),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(percentile(&sorted, 0f64), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&[], 50f64), None);
+    }
+
+    #[test]
+    fn custom_command_args() {
+        let options = PingOptions::new()
+            .program("/usr/bin/ping6")
+            .prefix(["ip", "netns", "exec", "blue"])
+            .extra_args(["-Q", "0x10"]);
+        let mut pinger = CustomCommand {
+            pinger: LinuxPinger::default(),
+            program: options.program.clone(),
+            prefix: options.prefix.clone(),
+            extra_args: options.extra_args.clone(),
+        };
+        pinger.set_options(options);
+        assert_eq!(pinger.program(), "ip");
+        assert_eq!(
+            pinger.ping_args("example.com".to_string()),
+            vec![
+                "netns",
+                "exec",
+                "blue",
+                "/usr/bin/ping6",
+                "-O",
+                "-i0.2",
+                "example.com",
+                "-Q",
+                "0x10"
+            ]
+        );
+
+        // Without a prefix the program is run directly
+        let pinger = CustomCommand {
+            pinger: LinuxPinger::default(),
+            program: Some("/opt/ping".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(pinger.program(), "/opt/ping");
+        assert_eq!(
+            pinger.ping_args("example.com".to_string()),
+            vec!["-O", "-i0.2", "example.com"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn custom_command_runs() {
+        // The prefix prints the command it was given a line at a time, in place of running it
+        let options = PingOptions::new()
+            .backend(Backend::Iputils)
+            .program("/opt/ping")
+            .prefix(["sh", "-c", "printf '%s\\n' \"$@\"", "sh"])
+            .extra_args(["64 bytes from 127.0.0.1: icmp_seq=1 ttl=64 time=0.04 ms"]);
+        let handle = crate::ping_with_options("example.com".to_string(), options).unwrap();
+        match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+            PingResult::Pong(_, reply) => assert_eq!(reply.seq, Some(1)),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    // Runs a long `sleep` in place of ping
+    #[derive(Default)]
+    struct SleepPinger {}
+
+    impl Pinger for SleepPinger {
+        fn set_interval(&mut self, _interval: Duration) {}
+
+        fn program(&self) -> &str {
+            "sleep"
+        }
+
+        fn ping_args(&self, _target: String) -> Vec<String> {
+            vec!["30".to_string()]
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn handle_stop_reaps_process() {
+        let mut handle = SleepPinger::default()
+            .start::<LinuxParser>("example.com".to_string())
+            .unwrap();
+        let pid = handle.child.as_ref().unwrap().lock().unwrap().id();
+        assert!(std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        handle.stop();
+        // Reaped, not left as a zombie
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        assert!(handle.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+
+    // Fails the same way ping does for an unknown host
+    #[derive(Default)]
+    struct FailingPinger {}
+
+    impl Pinger for FailingPinger {
+        fn set_interval(&mut self, _interval: Duration) {}
+
+        fn program(&self) -> &str {
+            "sh"
+        }
+
+        fn ping_args(&self, _target: String) -> Vec<String> {
+            vec![
+                "-c".to_string(),
+                "echo 'ping: example.invalid: Name or service not known' >&2; exit 2".to_string(),
+            ]
+        }
+    }
+
+    #[derive(Default)]
+    struct MissingPinger {}
+
+    impl Pinger for MissingPinger {
+        fn set_interval(&mut self, _interval: Duration) {}
+
+        fn program(&self) -> &str {
+            "/nonexistent/ping"
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn process_failure() {
+        let handle = FailingPinger::default()
+            .start::<LinuxParser>("example.invalid".to_string())
+            .unwrap();
+        match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+            PingResult::Error(stderr, status) => {
+                assert_eq!(stderr, "ping: example.invalid: Name or service not known");
+                assert_eq!(status.and_then(|s| s.code()), Some(2));
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert!(handle.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+
+    #[test]
+    fn spawn_failure() {
+        let error = MissingPinger::default()
+            .start::<LinuxParser>("example.com".to_string())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<PingError>(),
+            Some(PingError::SpawnFailed(..))
+        ));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn icmp_checksum() {
+        let packet =
+            icmp::echo_request("127.0.0.1".parse().unwrap(), 0x1234, 7, icmp::PAYLOAD_SIZE);
+        assert_eq!(icmp::checksum(&packet), 0);
+        assert_eq!(&packet[4..8], &[0x12, 0x34, 0, 7]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn icmp_parse_reply() {
+        let mut packet = icmp::echo_request("127.0.0.1".parse().unwrap(), 1, 2, icmp::PAYLOAD_SIZE);
+        packet[0] = 0;
+        let reply = icmp::parse_echo_reply(&packet, "127.0.0.1".parse().unwrap(), false);
+        assert_eq!(
+            reply,
+            Some(icmp::EchoReply {
+                ident: 1,
+                seq: 2,
+                size: 64,
+                ttl: None,
+            })
+        );
+        // Our own echo request looped back to a raw socket is not a reply
+        packet[0] = 8;
+        assert_eq!(
+            icmp::parse_echo_reply(&packet, "127.0.0.1".parse().unwrap(), false),
+            None
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn icmp_socket_loopback() {
+        let mut pinger = IcmpSocketPinger::default();
+        pinger.set_options(
+            PingOptions::new()
+                .interval(Duration::from_millis(50))
+                .count(3),
+        );
+        let stream = pinger
+            .start::<LinuxParser>("127.0.0.1".to_string())
+            .expect("Could not open an ICMP socket");
+        for _ in 0..3 {
+            match stream.recv_timeout(Duration::from_secs(2)).unwrap() {
+                PingResult::Pong(duration, reply) => {
+                    assert!(duration < Duration::from_millis(50));
+                    assert!(reply.line.starts_with("64 bytes from 127.0.0.1"));
+                    assert_eq!(reply.bytes, Some(64));
+                    assert_eq!(reply.from, Some("127.0.0.1".parse().unwrap()));
+                    assert!(reply.ttl.is_some(), "No TTL in {}", reply.line);
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        // The stream ends once the count is reached
+        assert!(stream.recv_timeout(Duration::from_secs(2)).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ping_count_loopback() {
+        let options = PingOptions::new()
+            .interval(Duration::from_millis(50))
+            .backend(Backend::NativeSocket);
+        let stats = crate::ping_count("127.0.0.1".to_string(), 3, options).unwrap();
+        assert_eq!((stats.sent, stats.received, stats.lost), (3, 3, 0));
+        assert!(stats.max.unwrap() < Duration::from_millis(50));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn trace_loopback() {
+        let localhost: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+
+        // The destination answers UDP probes with port unreachable, which arrives as an error
+        let hops = Tracer::new(Method::Udp)
+            .probes(2)
+            .trace("127.0.0.1")
+            .unwrap();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].ttl, 1);
+        assert_eq!(hops[0].responders, vec![localhost]);
+        assert_eq!(hops[0].rtts.len(), 2);
+        assert!(hops[0].rtts.iter().all(Option::is_some));
+        assert_eq!(hops[0].icmp_type, Some(3));
+
+        // And TCP probes by accepting the connection, or once nothing is listening, resetting it
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let tcp_trace = || {
+            Tracer::new(Method::Tcp)
+                .port(port)
+                .trace("127.0.0.1")
+                .unwrap()
+        };
+        let hops = tcp_trace();
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].responders, vec![localhost]);
+        assert_eq!(hops[0].icmp_type, None);
+        drop(listener);
+        assert_eq!(tcp_trace()[0].responders, vec![localhost]);
+    }
+
+    #[test]
+    fn tcp_listener() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let mut pinger = TcpPinger::default();
+        pinger.set_options(
+            PingOptions::new()
+                .interval(Duration::from_millis(50))
+                .count(2),
+        );
+        let handle = pinger.start::<LinuxParser>(target.clone()).unwrap();
+        for seq in 1..=2 {
+            match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+                PingResult::Pong(_, reply) => {
+                    assert_eq!(reply.seq, Some(seq));
+                    assert_eq!(reply.from, Some("127.0.0.1".parse().unwrap()));
+                    assert!(!reply.closed);
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        assert!(handle.recv_timeout(Duration::from_secs(2)).is_err());
+
+        // Nothing is listening once it's dropped, so the connection is reset
+        drop(listener);
+        pinger.set_options(PingOptions::new().count(1));
+        let handle = pinger.start::<LinuxParser>(target).unwrap();
+        match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+            PingResult::Pong(_, reply) => assert!(reply.closed, "{}", reply.line),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn tcp_needs_port() {
+        let error = TcpPinger::default()
+            .start::<LinuxParser>("127.0.0.1".to_string())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<PingError>(),
+            Some(PingError::HostnameError(..))
+        ));
+    }
+
+    #[test]
+    fn http_url() {
+        let url = Url::parse("https://[::1]:8443?q=1").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                tls: true,
+                host: "::1".to_string(),
+                port: 8443,
+                path: "/?q=1".to_string(),
+            }
+        );
+        let url = Url::parse("http://example.com/health").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/health"));
+        for invalid in [
+            "example.com",
+            "ftp://example.com",
+            "http://",
+            "http://host:port/",
+            "https://[::1]junk/",
+            "https://[::1]:/",
+        ] {
+            assert!(
+                matches!(Url::parse(invalid), Err(PingError::InvalidUrl(..))),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn http_server() {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let status = if request.starts_with(b"GET /down ") {
+                    "503 Service Unavailable"
+                } else {
+                    "200 OK"
+                };
+                // The connection is left open, so the response has to be read by its length
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 5\r\n\r\nhello",
+                    status
+                )
+                .unwrap();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_secs(10));
+                    drop(stream);
+                });
+            }
+        });
+
+        let mut pinger = HttpPinger::default();
+        pinger.set_options(
+            PingOptions::new()
+                .interval(Duration::from_millis(50))
+                .count(2),
+        );
+        let handle = pinger
+            .start::<LinuxParser>(format!("http://{}/", addr))
+            .unwrap();
+        for seq in 1..=2 {
+            match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+                PingResult::Pong(duration, reply) => {
+                    assert_eq!(reply.seq, Some(seq));
+                    assert_eq!(reply.bytes, Some(43));
+                    assert!(
+                        reply.line.starts_with("HTTP/1.1 200 OK from"),
+                        "{}",
+                        reply.line
+                    );
+                    let phases = reply.phases.unwrap();
+                    assert_eq!(phases.tls, None);
+                    assert!(phases.dns + phases.connect + phases.first_byte <= duration);
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        assert!(handle.recv_timeout(Duration::from_secs(2)).is_err());
+
+        pinger.set_options(PingOptions::new().count(1));
+        let handle = pinger
+            .start::<LinuxParser>(format!("http://{}/down", addr))
+            .unwrap();
+        match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+            PingResult::HttpError(status, reply) => {
+                assert_eq!(status, 503);
+                assert!(reply.phases.is_some());
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    // Answers queries for "example.test" once, says anything else doesn't exist, and truncates
+    // the answer for "big.test" so that it has to be asked again over TCP.
+    fn dns_responder() -> std::net::SocketAddr {
+        use std::io::{Read, Write};
+
+        fn respond(query: &[u8]) -> Vec<u8> {
+            let mut response = query.to_vec();
+            response[2] |= 0x80;
+            if query[12..].starts_with(b"\x07example\x04test\x00") {
+                response[7] = 1;
+            } else if !query[12..].starts_with(b"\x03big\x04test\x00") {
+                response[3] = 3;
+            }
+            response
+        }
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let listener = std::net::TcpListener::bind(addr).unwrap();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 512];
+            loop {
+                let (size, from) = socket.recv_from(&mut buffer).unwrap();
+                let mut response = respond(&buffer[..size]);
+                if buffer[12..size].starts_with(b"\x03big") {
+                    response[2] |= 0x02;
+                }
+                socket.send_to(&response, from).unwrap();
+            }
+        });
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).unwrap();
+                let mut query = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut query).unwrap();
+                let response = respond(&query);
+                stream
+                    .write_all(&(response.len() as u16).to_be_bytes())
+                    .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn dns_queries() {
+        let resolver = dns_responder().to_string();
+        let query = |name: &str| {
+            let mut pinger = DnsPinger::new(name, RecordType::A);
+            pinger.set_options(PingOptions::new().count(1));
+            let handle = pinger.start::<LinuxParser>(resolver.clone()).unwrap();
+            handle.recv_timeout(Duration::from_secs(2)).unwrap()
+        };
+
+        match query("example.test.") {
+            PingResult::Pong(_, reply) => {
+                assert_eq!(reply.seq, Some(1));
+                assert_eq!(reply.from, Some("127.0.0.1".parse().unwrap()));
+                assert!(
+                    reply.line.starts_with("NOERROR for example.test. A from"),
+                    "{}",
+                    reply.line
+                );
+                assert!(reply.line.contains("answers=1"), "{}", reply.line);
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        match query("big.test") {
+            PingResult::Pong(_, reply) => {
+                assert!(reply.line.contains("over tcp"), "{}", reply.line)
+            }
+            other => panic!("Unexpected result {:?}", other),
+        }
+        let missing = query("missing.test");
+        assert!(matches!(missing, PingResult::DnsError(3, _)));
+        assert_eq!(missing.to_string(), "DNS NXDOMAIN");
+    }
+
+    #[test]
+    fn dns_timeout() {
+        // Bound but never read from
+        let silent = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut pinger = DnsPinger::new("example.test", RecordType::Aaaa);
+        pinger.set_options(
+            PingOptions::new()
+                .count(1)
+                .timeout(Duration::from_millis(100)),
+        );
+        let handle = pinger
+            .start::<LinuxParser>(silent.local_addr().unwrap().to_string())
+            .unwrap();
+        match handle.recv_timeout(Duration::from_secs(2)).unwrap() {
+            PingResult::Timeout(line) => assert!(line.contains("example.test AAAA"), "{}", line),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dns_invalid_query() {
+        let long = "a".repeat(64);
+        let error = DnsPinger::new(long, RecordType::A)
+            .start::<LinuxParser>("127.0.0.1".to_string())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<PingError>(),
+            Some(PingError::InvalidQuery(..))
+        ));
+        assert_eq!("aaaa".parse::<RecordType>().unwrap(), RecordType::Aaaa);
+        assert!("bogus".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn udp_probe() {
+        let probe = Probe {
+            seq: 258,
+            sent: 1_600_000_000_000_000_000,
+        };
+        let packet = probe.encode(64);
+        assert_eq!(packet.len(), 64);
+        assert_eq!(Probe::decode(&packet), Some(probe));
+        // Too small to hold the header, so it's padded out
+        assert_eq!(probe.encode(8).len(), 20);
+        assert_eq!(Probe::decode(b"not a probe, just some junk"), None);
+    }
+
+    #[test]
+    fn udp_reflector() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap().to_string();
+        std::thread::spawn(move || crate::udp::reflect(&socket));
+
+        let mut pinger = UdpPinger::default();
+        pinger.set_options(
+            PingOptions::new()
+                .interval(Duration::from_millis(20))
+                .count(3)
+                .size(100),
+        );
+        let results: Vec<PingResult> = pinger.start::<LinuxParser>(target).unwrap().collect();
+        assert_eq!(results.len(), 3);
+        for (seq, result) in (1..=3).zip(&results) {
+            match result {
+                PingResult::Pong(_, reply) => {
+                    assert_eq!(reply.seq, Some(seq));
+                    assert_eq!(reply.bytes, Some(100));
+                    assert!(!reply.reordered && !reply.late && !reply.duplicate);
+                }
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn outstanding_probes() {
+        let start = Instant::now();
+        let mut outstanding = Outstanding::new();
+        outstanding.sent(1, start);
+        outstanding.sent(2, start + Duration::from_secs(1));
+        assert_eq!(outstanding.oldest(), Some(start));
+        // Half a second after the second probe, with a timeout of one second
+        assert_eq!(
+            outstanding.expire(start + Duration::from_millis(500)),
+            vec![1]
+        );
+
+        let (duration, reply) = outstanding
+            .reply(2, start + Duration::from_millis(1100))
+            .unwrap();
+        assert_eq!(duration, Duration::from_millis(100));
+        assert!(!reply.late && !reply.reordered && !reply.duplicate);
+        assert!(outstanding.is_empty());
+
+        // Still has a round trip time after timing out
+        let (duration, reply) = outstanding
+            .reply(1, start + Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(duration, Duration::from_secs(2));
+        assert!(reply.late && reply.reordered);
+
+        assert!(
+            outstanding
+                .reply(2, start + Duration::from_secs(3))
+                .unwrap()
+                .1
+                .duplicate
+        );
+        assert!(outstanding
+            .reply(7, start + Duration::from_secs(3))
+            .is_none());
+    }
+
+    #[test]
+    fn udp_loss_and_reordering() {
+        // Drops the second probe and holds on to the third until the fourth has been echoed
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let target = socket.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 1024];
+            let mut held = None;
+            loop {
+                let (size, from) = socket.recv_from(&mut buffer).unwrap();
+                let packet = buffer[..size].to_vec();
+                match Probe::decode(&packet).unwrap().seq {
+                    2 => {}
+                    3 => held = Some(packet),
+                    _ => {
+                        socket.send_to(&packet, from).unwrap();
+                        if let Some(held) = held.take() {
+                            socket.send_to(&held, from).unwrap();
+                            socket.send_to(&held, from).unwrap();
+                        }
+                    }
+                }
+            }
+        });
+
+        let mut pinger = UdpPinger::default();
+        pinger.set_options(
+            PingOptions::new()
+                .interval(Duration::from_millis(20))
+                .timeout(Duration::from_millis(300))
+                .count(4),
+        );
+        let results: Vec<PingResult> = pinger.start::<LinuxParser>(target).unwrap().collect();
+        let described: Vec<String> = results
+            .iter()
+            .map(|result| match result {
+                PingResult::Pong(_, reply) => format!(
+                    "seq={}{}{}",
+                    reply.seq.unwrap(),
+                    if reply.reordered { " reordered" } else { "" },
+                    if reply.duplicate { " dup" } else { "" }
+                ),
+                other => other.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec!["seq=1", "seq=4", "seq=3 reordered", "seq=3 dup", "Timeout"]
+        );
+        assert!(matches!(&results[4], PingResult::Timeout(line) if line.ends_with("udp_seq=2")));
+
+        let mut collector = StatsCollector::new();
+        results.iter().for_each(|result| collector.add(result));
+        let stats = collector.stats();
+        assert_eq!(
+            (
+                stats.sent,
+                stats.received,
+                stats.duplicates,
+                stats.reordered
+            ),
+            (4, 3, 1, 1)
+        );
+    }
+
+    // Writes a file to replay, which is removed when it's dropped
+    struct Recording(std::path::PathBuf);
+
+    impl Recording {
+        fn new(name: &str, contents: &str) -> Recording {
+            let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            std::fs::write(&path, contents).unwrap();
+            Recording(path)
+        }
+
+        fn path(&self) -> String {
+            self.0.to_string_lossy().into_owned()
+        }
+    }
+
+    impl Drop for Recording {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn replay_transcript() {
+        let pinger = ReplayPinger::default().speed(f64::INFINITY);
+        let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/ubuntu.txt");
+        let results: Vec<PingResult> = pinger
+            .start::<LinuxParser>(fixture.to_string())
+            .unwrap()
+            .collect();
+        let pongs = results
+            .iter()
+            .filter(|r| matches!(r, PingResult::Pong(..)))
+            .count();
+        let timeouts = results
+            .iter()
+            .filter(|r| matches!(r, PingResult::Timeout(..)))
+            .count();
+        assert_eq!((pongs, timeouts), (10, 2));
+        assert_eq!(
+            ReplayPinger::targets(fixture).unwrap(),
+            vec!["ubuntu.txt".to_string()]
+        );
+    }
+
+    #[test]
+    fn replay_pacing() {
+        let recording = Recording::new(
+            "pacing.txt",
+            "[1634567890.000000] 64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=1.00 ms\n\
+             [1634567891.000000] 64 bytes from 10.0.0.1: icmp_seq=2 ttl=64 time=1.00 ms\n\
+             64 bytes from 10.0.0.1: icmp_seq=5 ttl=64 time=1.00 ms\n",
+        );
+        // Ten times faster than a second apart
+        let pinger = ReplayPinger::default().speed(10f64);
+        let started = Instant::now();
+        let results: Vec<PingResult> = pinger
+            .start::<LinuxParser>(recording.path())
+            .unwrap()
+            .collect();
+        let seqs: Vec<Option<u64>> = results.iter().map(result_seq).collect();
+        assert_eq!(seqs, [Some(1), Some(2), Some(5)]);
+        // The last comes three intervals of 0.2s after the second, so at 0.16s
+        assert!(started.elapsed() >= Duration::from_millis(160));
+
+        // Without timestamps the gap in sequence numbers is kept
+        let recording = Recording::new(
+            "gap.txt",
+            "64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=1.00 ms\n\
+             64 bytes from 10.0.0.1: icmp_seq=5 ttl=64 time=1.00 ms\n",
+        );
+        let mut pinger = ReplayPinger::default().speed(2f64);
+        pinger.set_interval(Duration::from_millis(100));
+        let started = Instant::now();
+        assert_eq!(
+            pinger
+                .start::<LinuxParser>(recording.path())
+                .unwrap()
+                .count(),
+            2
+        );
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn replay_stop() {
+        let recording = Recording::new(
+            "slow.txt",
+            "64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=1.00 ms\n\
+             64 bytes from 10.0.0.1: icmp_seq=100 ttl=64 time=1.00 ms\n",
+        );
+        let pinger = ReplayPinger::default();
+        let mut handle = pinger.start::<LinuxParser>(recording.path()).unwrap();
+        assert!(matches!(handle.recv(), Ok(PingResult::Pong(..))));
+        // The replay ends as soon as it's stopped, rather than waiting out the gap first
+        let started = Instant::now();
+        handle.stop();
+        assert!(handle.recv().is_err());
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn replay_csv() {
+        let recording = Recording::new(
+            "log.csv",
+            ",12,30,Average\n\
+             ,14,1000,95th percentile\n\
+             Time, Gateway, ISP Hop 1\n\
+             0.0,10,20,\n\
+             0.2,14,1000,\n",
+        );
+        assert_eq!(
+            ReplayPinger::targets(&recording.0).unwrap(),
+            vec!["Gateway".to_string(), "ISP Hop 1".to_string()]
+        );
+        let pinger = ReplayPinger::default().speed(f64::INFINITY).column(1);
+        let results: Vec<String> = pinger
+            .start::<LinuxParser>(recording.path())
+            .unwrap()
+            .map(|result| describe(&result))
+            .collect();
+        assert_eq!(results, vec!["20ms seq=1", "Timeout"]);
+
+        let error = ReplayPinger::default()
+            .start::<LinuxParser>("/nonexistent/ping.csv".to_string())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error.downcast_ref::<PingError>(),
+            Some(PingError::Failed(..))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn process_stream() {
+        use futures::StreamExt;
+
+        let mut command = tokio::process::Command::new("cat");
+        command.arg(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/ubuntu.txt"));
+        let pending = vec![PingResult::Warning("slower".to_string())].into();
+        let stream = crate::stream::from_command::<LinuxParser>(
+            "cat".to_string(),
+            command,
+            SeqTracker::reconciling(),
+            pending,
+        )
+        .unwrap();
+        let results: Vec<PingResult> = stream.collect().await;
+        assert!(matches!(results[0], PingResult::Warning(_)));
+        let pongs = results
+            .iter()
+            .filter(|r| matches!(r, PingResult::Pong(..)))
+            .count();
+        let timeouts = results
+            .iter()
+            .filter(|r| matches!(r, PingResult::Timeout(..)))
+            .count();
+        assert_eq!((pongs, timeouts), (10, 2));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn process_stream_error() {
+        use futures::StreamExt;
+
+        let mut command = tokio::process::Command::new("sh");
+        command.args([
+            "-c",
+            "echo 'ping: nowhere: Name or service not known' >&2; exit 2",
+        ]);
+        let stream = crate::stream::from_command::<LinuxParser>(
+            "sh".to_string(),
+            command,
+            SeqTracker::reconciling(),
+            std::collections::VecDeque::new(),
+        )
+        .unwrap();
+        let results: Vec<PingResult> = stream.collect().await;
+        match &results[..] {
+            [PingResult::Error(stderr, Some(status))] => {
+                assert_eq!(stderr, "ping: nowhere: Name or service not known");
+                assert_eq!(status.code(), Some(2));
+            }
+            results => panic!("unexpected results {:?}", results),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn process_stream_tracker() {
+        use futures::StreamExt;
+
+        // Like busybox, which says nothing about requests that weren't answered
+        let mut command = tokio::process::Command::new("sh");
+        command.args([
+            "-c",
+            "echo '64 bytes from 192.0.2.1: seq=0 ttl=57 time=1.0 ms'; sleep 1",
+        ]);
+        let interval = Duration::from_millis(50);
+        let tracker = SeqTracker::new(interval, interval, Instant::now());
+        let stream = crate::stream::from_command::<LinuxParser>(
+            "sh".to_string(),
+            command,
+            tracker,
+            std::collections::VecDeque::new(),
+        )
+        .unwrap();
+        let results: Vec<PingResult> = stream.take(2).collect().await;
+        assert!(matches!(&results[0], PingResult::Pong(_, reply) if reply.seq == Some(0)));
+        assert!(matches!(&results[1], PingResult::Timeout(line) if line.ends_with("icmp_seq=1")));
+    }
+
+    #[cfg(all(feature = "async", target_os = "linux"))]
+    #[tokio::test]
+    async fn socket_stream() {
+        use crate::tracker::Outstanding;
+        use crate::ProbeSocket;
+        use futures::StreamExt;
+        use std::net::UdpSocket;
+        use std::os::unix::io::{AsRawFd, RawFd};
+
+        // Sends each sequence number as it is, reading back whatever comes back
+        struct Echo(UdpSocket);
+
+        impl ProbeSocket for Echo {
+            fn send(&mut self, seq: u64) {
+                let _ = self.0.send(&seq.to_be_bytes());
+            }
+
+            fn receive(
+                &mut self,
+                outstanding: &mut Outstanding,
+            ) -> std::io::Result<Option<PingResult>> {
+                let mut buffer = [0; 8];
+                self.0.recv(&mut buffer)?;
+                let seq = u64::from_be_bytes(buffer);
+                Ok(outstanding
+                    .reply(seq, Instant::now())
+                    .map(|(duration, reply)| {
+                        PingResult::Pong(
+                            duration,
+                            PingReply {
+                                seq: Some(seq),
+                                ..reply
+                            },
+                        )
+                    }))
+            }
+
+            fn set_read_timeout(&self, wait: Duration) -> std::io::Result<()> {
+                self.0.set_read_timeout(Some(wait))
+            }
+
+            fn timed_out(&self, seq: u64) -> PingResult {
+                PingResult::Timeout(format!("seq={}", seq))
+            }
+        }
+
+        impl AsRawFd for Echo {
+            fn as_raw_fd(&self) -> RawFd {
+                self.0.as_raw_fd()
+            }
+        }
+
+        fn connect(to: &UdpSocket) -> Echo {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket.connect(to.local_addr().unwrap()).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            Echo(socket)
+        }
+
+        let options = PingOptions::default()
+            .interval(Duration::from_millis(10))
+            .count(3);
+        let echo = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = connect(&echo);
+        std::thread::spawn(move || {
+            let mut buffer = [0; 8];
+            while let Ok((size, from)) = echo.recv_from(&mut buffer) {
+                let _ = echo.send_to(&buffer[..size], from);
+            }
+        });
+        let stream = crate::stream::from_socket(
+            options.clone(),
+            Duration::from_secs(1),
+            socket,
+            std::collections::VecDeque::new(),
+        )
+        .unwrap();
+        let seqs: Vec<Option<u64>> = stream.map(|result| result_seq(&result)).collect().await;
+        assert_eq!(seqs, vec![Some(1), Some(2), Some(3)]);
+
+        // Nothing answers, so every probe times out and the stream ends
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stream = crate::stream::from_socket(
+            options,
+            Duration::from_millis(50),
+            connect(&silent),
+            std::collections::VecDeque::new(),
+        )
+        .unwrap();
+        let results: Vec<PingResult> = stream.collect().await;
+        assert_eq!(results.len(), 3);
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, PingResult::Timeout(_))));
+    }
+
+    #[cfg(all(feature = "async", windows))]
+    #[test]
+    fn bridge_drop() {
+        let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stream = crate::stream::bridge(
+            PingHandle::new(rx, Arc::clone(&stop)),
+            std::collections::VecDeque::new(),
+        );
+        drop(stream);
+        // Nothing is sent, so the handle has to notice by itself
+        let started = Instant::now();
+        while !stop.load(std::sync::atomic::Ordering::Acquire) {
+            assert!(started.elapsed() < Duration::from_secs(2));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(tx);
     }
 }
@@ -0,0 +1,366 @@
+//! Finds the route to a host the way `traceroute` does, by sending probes with increasing TTLs
+//! and seeing who answers, without needing it installed. Linux only.
+//!
+//! Probes are sent from ordinary sockets with `IP_RECVERR` set, so the time exceeded errors that
+//! routers send back get queued on the socket that sent the probe, along with who sent them.
+//! UDP and TCP probes don't need any privileges. ICMP probes need ping sockets to be allowed by
+//! `net.ipv4.ping_group_range`, as for [`crate::linux::IcmpSocketPinger`].
+//! ```no_run
+//! use pinger::trace::{Method, Tracer};
+//!
+//! for hop in Tracer::new(Method::Udp).start("tomforb.es")? {
+//!     println!("{} {:?} {:?}", hop.ttl, hop.responders, hop.rtts);
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+use crate::{icmp, resolve, IpVersion};
+use anyhow::Result;
+use socket2::{Domain, SockAddr, Socket, Type};
+use std::io;
+use std::io::ErrorKind;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// Where UDP probes are sent, counting up for each one, unless a port is given.
+pub const DEFAULT_UDP_PORT: u16 = 33434;
+/// Where TCP probes connect to unless a port is given.
+pub const DEFAULT_TCP_PORT: u16 = 80;
+
+const ECHO_REPLY_V4: u8 = 0;
+const ECHO_REPLY_V6: u8 = 129;
+const UNREACHABLE_V4: u8 = 3;
+const UNREACHABLE_V6: u8 = 1;
+
+/// What kind of probe to send.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Echo requests, answered by the destination with an echo reply.
+    Icmp,
+    /// Datagrams to an unlikely port, answered by the destination with port unreachable.
+    Udp,
+    /// Connections, answered by the destination accepting or resetting them. Useful where only
+    /// web traffic gets through.
+    Tcp,
+}
+
+/// Everything that happened to the probes sent with one TTL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub ttl: u8,
+    /// Everyone who answered, in the order they first did. Load balanced paths can have more
+    /// than one.
+    pub responders: Vec<IpAddr>,
+    /// The round trip time of each probe, or `None` if it wasn't answered.
+    pub rtts: Vec<Option<Duration>>,
+    /// The ICMP type of the last answer, e.g. time exceeded from a router. `None` if the last
+    /// answer wasn't an ICMP message, as when the destination accepts a TCP connection.
+    pub icmp_type: Option<u8>,
+}
+
+impl Hop {
+    pub fn responded(&self) -> bool {
+        !self.responders.is_empty()
+    }
+}
+
+pub struct Tracer {
+    method: Method,
+    max_hops: u8,
+    probes: usize,
+    timeout: Duration,
+    port: Option<u16>,
+    ip_version: Option<IpVersion>,
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Tracer::new(Method::Udp)
+    }
+}
+
+impl Tracer {
+    pub fn new(method: Method) -> Self {
+        Tracer {
+            method,
+            max_hops: 30,
+            probes: 3,
+            timeout: Duration::from_secs(1),
+            port: None,
+            ip_version: None,
+        }
+    }
+
+    /// The highest TTL to try before giving up on reaching the destination.
+    pub fn max_hops(mut self, max_hops: u8) -> Self {
+        self.max_hops = max_hops;
+        self
+    }
+
+    /// How many probes to send with each TTL.
+    pub fn probes(mut self, probes: usize) -> Self {
+        self.probes = probes;
+        self
+    }
+
+    /// How long to wait for the probes of each hop to be answered.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The port to send UDP probes to, or connect TCP probes to. Ignored for ICMP.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn ip_version(mut self, ip_version: IpVersion) -> Self {
+        self.ip_version = Some(ip_version);
+        self
+    }
+
+    /// Starts tracing the route to a host, one hop each time the returned iterator is advanced.
+    /// It ends after the destination answers, or a router says it can't be reached.
+    pub fn start(self, target: &str) -> Result<Trace> {
+        let target = resolve(target, Some(0), self.ip_version)?.ip();
+        // Fail now rather than with every probe, e.g. if ping sockets aren't allowed
+        open_socket(self.method, target, 1)?;
+        Ok(Trace {
+            tracer: self,
+            target,
+            ttl: 1,
+            sent: 0,
+            done: false,
+        })
+    }
+
+    /// Traces the whole route to a host.
+    pub fn trace(self, target: &str) -> Result<Vec<Hop>> {
+        Ok(self.start(target)?.collect())
+    }
+}
+
+/// A trace in progress, see [`Tracer::start`].
+pub struct Trace {
+    tracer: Tracer,
+    target: IpAddr,
+    ttl: u8,
+    // How many probes have been sent, which numbers them
+    sent: u16,
+    done: bool,
+}
+
+// Where a probe is up to
+enum Probe {
+    Waiting(Socket, Instant),
+    Answered(IpAddr, Option<u8>, Duration),
+    // Failed to send, or failed in a way that won't be answered
+    Lost,
+}
+
+impl Trace {
+    /// The address being traced to.
+    pub fn target(&self) -> IpAddr {
+        self.target
+    }
+
+    fn send(&mut self) -> io::Result<(Socket, Instant)> {
+        self.sent = self.sent.wrapping_add(1);
+        let port = match self.tracer.method {
+            Method::Icmp => 0,
+            Method::Udp => self
+                .tracer
+                .port
+                .unwrap_or_else(|| DEFAULT_UDP_PORT.wrapping_add(self.sent)),
+            Method::Tcp => self.tracer.port.unwrap_or(DEFAULT_TCP_PORT),
+        };
+        let socket = open_socket(self.tracer.method, self.target, self.ttl)?;
+        let addr = SockAddr::from(SocketAddr::new(self.target, port));
+        match socket.connect(&addr) {
+            Ok(_) => {}
+            // Non-blocking connections carry on in the background
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(e) => return Err(e),
+        }
+        let sent = Instant::now();
+        match self.tracer.method {
+            Method::Icmp => {
+                let request = icmp::echo_request(self.target, 0, self.sent, icmp::PAYLOAD_SIZE);
+                socket.send(&request)?;
+            }
+            Method::Udp => {
+                socket.send(&[0u8; 32])?;
+            }
+            Method::Tcp => {}
+        }
+        Ok((socket, sent))
+    }
+
+    // Checks a probe whose socket has something for us
+    fn receive(&self, socket: &Socket, sent: Instant) -> Option<Probe> {
+        // Errors are queued first, and a TCP connection reports failing after them
+        match icmp::recv_error(socket) {
+            Ok(Some(error)) => {
+                return Some(Probe::Answered(
+                    error.from,
+                    Some(error.icmp_type),
+                    sent.elapsed(),
+                ))
+            }
+            Ok(None) => return Some(Probe::Lost),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => return Some(Probe::Lost),
+        }
+        let answered = |icmp_type| Some(Probe::Answered(self.target, icmp_type, sent.elapsed()));
+        match self.tracer.method {
+            Method::Icmp => {
+                let mut buffer = [0u8; 1500];
+                let size = match icmp::recv(socket, &mut buffer) {
+                    Ok((size, _)) => size,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => return None,
+                    Err(_) => return Some(Probe::Lost),
+                };
+                // Ping sockets take care of the identifier, and the socket is only for this probe
+                icmp::parse_echo_reply(&buffer[..size], self.target, false)?;
+                answered(Some(match self.target {
+                    IpAddr::V4(_) => ECHO_REPLY_V4,
+                    IpAddr::V6(_) => ECHO_REPLY_V6,
+                }))
+            }
+            Method::Udp => match socket.recv(&mut [std::mem::MaybeUninit::uninit(); 64]) {
+                Ok(_) => answered(None),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+                Err(_) => Some(Probe::Lost),
+            },
+            // Accepted or reset, either way it was the destination
+            Method::Tcp => match socket.take_error() {
+                Ok(None) => answered(None),
+                Ok(Some(e)) if e.kind() == ErrorKind::ConnectionRefused => answered(None),
+                _ => Some(Probe::Lost),
+            },
+        }
+    }
+
+    fn probe_hop(&mut self) -> Hop {
+        let mut probes: Vec<Probe> = (0..self.tracer.probes)
+            .map(|_| match self.send() {
+                Ok((socket, sent)) => Probe::Waiting(socket, sent),
+                Err(_) => Probe::Lost,
+            })
+            .collect();
+        let deadline = Instant::now() + self.tracer.timeout;
+        // Errors are always reported. Datagram sockets can always be written to, so only TCP
+        // waits for that, to hear that it connected.
+        let events = match self.tracer.method {
+            Method::Tcp => libc::POLLOUT,
+            Method::Icmp | Method::Udp => libc::POLLIN,
+        };
+
+        loop {
+            let mut fds: Vec<(usize, libc::pollfd)> = probes
+                .iter()
+                .enumerate()
+                .filter_map(|(i, probe)| match probe {
+                    Probe::Waiting(socket, _) => Some((
+                        i,
+                        libc::pollfd {
+                            fd: socket.as_raw_fd(),
+                            events,
+                            revents: 0,
+                        },
+                    )),
+                    _ => None,
+                })
+                .collect();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if fds.is_empty() || remaining.is_zero() {
+                break;
+            }
+            let mut pollfds: Vec<libc::pollfd> = fds.iter().map(|(_, fd)| *fd).collect();
+            let timeout = remaining.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+            let ready =
+                unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+            if ready < 0 && io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+                break;
+            }
+            for ((i, _), fd) in fds.iter_mut().zip(pollfds) {
+                if fd.revents == 0 {
+                    continue;
+                }
+                let update = match &probes[*i] {
+                    Probe::Waiting(socket, sent) => self.receive(socket, *sent),
+                    _ => None,
+                };
+                if let Some(update) = update {
+                    probes[*i] = update;
+                }
+            }
+        }
+
+        let mut hop = Hop {
+            ttl: self.ttl,
+            responders: Vec::new(),
+            rtts: Vec::with_capacity(probes.len()),
+            icmp_type: None,
+        };
+        for probe in probes {
+            match probe {
+                Probe::Answered(from, icmp_type, rtt) => {
+                    if !hop.responders.contains(&from) {
+                        hop.responders.push(from);
+                    }
+                    hop.rtts.push(Some(rtt));
+                    hop.icmp_type = icmp_type;
+                }
+                _ => hop.rtts.push(None),
+            }
+        }
+        hop
+    }
+}
+
+impl Iterator for Trace {
+    type Item = Hop;
+
+    fn next(&mut self) -> Option<Hop> {
+        if self.done || self.ttl > self.tracer.max_hops {
+            return None;
+        }
+        let hop = self.probe_hop();
+        let unreachable = match self.target {
+            IpAddr::V4(_) => UNREACHABLE_V4,
+            IpAddr::V6(_) => UNREACHABLE_V6,
+        };
+        // The TTL can't go past 255, so stop on the last hop rather than after it
+        self.done = hop.responders.contains(&self.target)
+            || hop.icmp_type == Some(unreachable)
+            || self.ttl >= self.tracer.max_hops;
+        self.ttl = self.ttl.saturating_add(1);
+        Some(hop)
+    }
+}
+
+fn open_socket(method: Method, target: IpAddr, ttl: u8) -> io::Result<Socket> {
+    let domain = match target {
+        IpAddr::V4(_) => Domain::IPV4,
+        IpAddr::V6(_) => Domain::IPV6,
+    };
+    let socket = match (method, target) {
+        (Method::Icmp, IpAddr::V4(_)) => {
+            Socket::new(domain, Type::DGRAM, Some(socket2::Protocol::ICMPV4))?
+        }
+        (Method::Icmp, IpAddr::V6(_)) => {
+            Socket::new(domain, Type::DGRAM, Some(socket2::Protocol::ICMPV6))?
+        }
+        (Method::Udp, _) => Socket::new(domain, Type::DGRAM, Some(socket2::Protocol::UDP))?,
+        (Method::Tcp, _) => Socket::new(domain, Type::STREAM, Some(socket2::Protocol::TCP))?,
+    };
+    match target {
+        IpAddr::V4(_) => socket.set_ttl(ttl as u32)?,
+        IpAddr::V6(_) => socket.set_unicast_hops_v6(ttl as u32)?,
+    }
+    icmp::enable_errors(&socket, target)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
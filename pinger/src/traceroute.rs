@@ -0,0 +1,383 @@
+//! Parses what route tracing programs print into hops, for platforms where [`crate::trace`]
+//! isn't available and the route has to be found by running one.
+//!
+//! Understands Linux and busybox `traceroute`, `tracepath`, macOS `traceroute` and Windows
+//! `tracert`, with or without names being looked up. Every probe is kept, along with who answered
+//! it, the `!H` style annotations `traceroute` adds when a probe is refused and any MPLS labels
+//! that routers quoted back.
+//! ```
+//! use pinger::traceroute::parse;
+//!
+//! let hops = parse(" 1  192.168.1.1  0.512 ms  0.480 ms *\n 2  * * *\n");
+//! assert_eq!(hops[0].responders(), ["192.168.1.1".parse::<std::net::IpAddr>()?]);
+//! assert!(!hops[1].responded());
+//! # Ok::<(), std::net::AddrParseError>(())
+//! ```
+use std::fmt;
+use std::fmt::Formatter;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Why a probe was refused, from the annotation after its round trip time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation {
+    /// `!H`
+    HostUnreachable,
+    /// `!N`
+    NetworkUnreachable,
+    /// `!P`
+    ProtocolUnreachable,
+    /// `!S`
+    SourceRouteFailed,
+    /// `!F`, with the next hop's MTU when it's given as in `!F-1400`.
+    FragmentationNeeded(Option<u32>),
+    /// `!X`, communication administratively prohibited.
+    Prohibited,
+    /// `!V`
+    PrecedenceViolation,
+    /// `!C`
+    PrecedenceCutoff,
+    /// Any other ICMP unreachable code, as in `!<10>`.
+    Code(u8),
+    /// Anything else, without the `!`.
+    Other(String),
+}
+
+impl Annotation {
+    fn parse(token: &str) -> Option<Annotation> {
+        let annotation = token.strip_prefix('!')?;
+        Some(match annotation {
+            "H" => Annotation::HostUnreachable,
+            "N" => Annotation::NetworkUnreachable,
+            "P" => Annotation::ProtocolUnreachable,
+            "S" => Annotation::SourceRouteFailed,
+            "F" => Annotation::FragmentationNeeded(None),
+            "X" => Annotation::Prohibited,
+            "V" => Annotation::PrecedenceViolation,
+            "C" => Annotation::PrecedenceCutoff,
+            _ => {
+                if let Some(mtu) = annotation.strip_prefix("F-") {
+                    Annotation::FragmentationNeeded(mtu.parse().ok())
+                } else if let Ok(code) = annotation
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .parse()
+                {
+                    Annotation::Code(code)
+                } else {
+                    Annotation::Other(annotation.to_string())
+                }
+            }
+        })
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Annotation::HostUnreachable => write!(f, "!H"),
+            Annotation::NetworkUnreachable => write!(f, "!N"),
+            Annotation::ProtocolUnreachable => write!(f, "!P"),
+            Annotation::SourceRouteFailed => write!(f, "!S"),
+            Annotation::FragmentationNeeded(None) => write!(f, "!F"),
+            Annotation::FragmentationNeeded(Some(mtu)) => write!(f, "!F-{}", mtu),
+            Annotation::Prohibited => write!(f, "!X"),
+            Annotation::PrecedenceViolation => write!(f, "!V"),
+            Annotation::PrecedenceCutoff => write!(f, "!C"),
+            Annotation::Code(code) => write!(f, "!<{}>", code),
+            Annotation::Other(other) => write!(f, "!{}", other),
+        }
+    }
+}
+
+/// An entry from the MPLS label stack a router quoted back with its ICMP extensions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MplsLabel {
+    pub label: u32,
+    /// The traffic class, called EXP by Linux and CoS by macOS.
+    pub exp: u8,
+    /// Whether this is the bottom of the stack.
+    pub bottom: bool,
+    pub ttl: u8,
+}
+
+impl MplsLabel {
+    // "L=24001,E=0,S=1,T=1" from Linux, or "Label=24001 CoS=0 TTL=1 S=1" from macOS
+    fn parse<'a>(fields: impl Iterator<Item = &'a str>) -> Option<MplsLabel> {
+        let (mut label, mut exp, mut bottom, mut ttl) = (None, None, None, None);
+        for field in fields {
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "L" | "Label" => label = value.parse().ok(),
+                "E" | "CoS" => exp = value.parse().ok(),
+                "S" => bottom = Some(value == "1"),
+                "T" | "TTL" => ttl = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(MplsLabel {
+            label: label?,
+            exp: exp?,
+            bottom: bottom?,
+            ttl: ttl?,
+        })
+    }
+}
+
+impl fmt::Display for MplsLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "L={},E={},S={},T={}",
+            self.label, self.exp, self.bottom as u8, self.ttl
+        )
+    }
+}
+
+/// One probe sent with a hop's TTL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Probe {
+    /// The address that answered, if it was printed.
+    pub addr: Option<IpAddr>,
+    /// The name that answered, if names were looked up. `tracepath` only prints the name.
+    pub name: Option<String>,
+    /// `None` if nothing answered in time.
+    pub rtt: Option<Duration>,
+    pub annotation: Option<Annotation>,
+    pub mpls: Vec<MplsLabel>,
+}
+
+impl Probe {
+    pub fn responded(&self) -> bool {
+        self.addr.is_some() || self.name.is_some()
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (&self.name, self.addr) {
+            (Some(name), Some(addr)) => write!(f, "{} ({})", name, addr)?,
+            (Some(name), None) => write!(f, "{}", name)?,
+            (None, Some(addr)) => write!(f, "{}", addr)?,
+            (None, None) => write!(f, "*")?,
+        }
+        if let Some(rtt) = self.rtt {
+            write!(f, " {:.3}ms", rtt.as_secs_f64() * 1_000f64)?;
+        }
+        if let Some(annotation) = &self.annotation {
+            write!(f, " {}", annotation)?;
+        }
+        if !self.mpls.is_empty() {
+            let labels: Vec<String> = self.mpls.iter().map(ToString::to_string).collect();
+            write!(f, " <MPLS:{}>", labels.join("/"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Every probe sent with one TTL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub ttl: u8,
+    pub probes: Vec<Probe>,
+}
+
+impl Hop {
+    /// The addresses that answered, in the order they first did. Load balanced paths can have
+    /// more than one.
+    pub fn responders(&self) -> Vec<IpAddr> {
+        let mut responders = Vec::new();
+        for addr in self.probes.iter().filter_map(|probe| probe.addr) {
+            if !responders.contains(&addr) {
+                responders.push(addr);
+            }
+        }
+        responders
+    }
+
+    pub fn responded(&self) -> bool {
+        self.probes.iter().any(Probe::responded)
+    }
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let probes: Vec<String> = self.probes.iter().map(ToString::to_string).collect();
+        write!(f, "{}: {}", self.ttl, probes.join(", "))
+    }
+}
+
+/// Parses output a line at a time, so that hops can be used while the trace is still running.
+#[derive(Default)]
+pub struct TracerouteParser {
+    hop: Option<Hop>,
+    // Who answered the last probe. Later probes on the line were answered by them too, unless
+    // someone else is printed.
+    responder: (Option<IpAddr>, Option<String>),
+}
+
+impl TracerouteParser {
+    /// Returns the previous hop once a line starts the next one, as macOS prints extra
+    /// responders and MPLS labels on lines of their own.
+    pub fn parse(&mut self, line: &str) -> Option<Hop> {
+        let mut tokens = line.split_whitespace().peekable();
+        // "1", or "1:" and "1?:" from tracepath
+        let ttl = tokens.peek().and_then(|token| {
+            token
+                .trim_end_matches(':')
+                .trim_end_matches('?')
+                .parse()
+                .ok()
+        });
+        let finished = match (ttl, &self.hop) {
+            (Some(ttl), Some(hop)) if hop.ttl == ttl => None,
+            (Some(ttl), _) => self.hop.replace(Hop {
+                ttl,
+                probes: Vec::new(),
+            }),
+            // The headers and footers aren't indented, but the lines that continue a hop are
+            (None, Some(_)) if line.starts_with(char::is_whitespace) => None,
+            (None, _) => return None,
+        };
+        if ttl.is_some() {
+            tokens.next();
+            self.responder = (None, None);
+        }
+        let tokens: Vec<&str> = tokens.collect();
+        self.parse_probes(&tokens);
+        finished
+    }
+
+    /// Returns the last hop, once there's no more output.
+    pub fn finish(&mut self) -> Option<Hop> {
+        self.hop.take()
+    }
+
+    fn parse_probes(&mut self, tokens: &[&str]) {
+        let hop = match &mut self.hop {
+            Some(hop) => hop,
+            None => return,
+        };
+        let line_start = hop.probes.len();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = tokens[i];
+            let next = tokens.get(i + 1).copied();
+            i += 1;
+            match token {
+                "*" => hop.probes.push(Probe::default()),
+                // tracepath
+                "no" if next == Some("reply") => {
+                    hop.probes.push(Probe::default());
+                    i += 1;
+                }
+                "asymm" | "pmtu" => i += 1,
+                // A label on a line of its own from macOS, for the probe before
+                "MPLS" => {
+                    if let (Some(probe), Some(label)) = (
+                        hop.probes.last_mut(),
+                        MplsLabel::parse(tokens[i..].iter().copied()),
+                    ) {
+                        probe.mpls.push(label);
+                    }
+                    return;
+                }
+                _ if token.starts_with("<MPLS:") => {
+                    let labels = token
+                        .trim_start_matches("<MPLS:")
+                        .trim_end_matches('>')
+                        .split('/')
+                        .filter_map(|label| MplsLabel::parse(label.split(',')));
+                    if let Some(probe) = hop.probes.last_mut() {
+                        probe.mpls.extend(labels);
+                    }
+                }
+                _ if token.starts_with('!') => {
+                    if let Some(probe) = hop.probes.last_mut() {
+                        probe.annotation = Annotation::parse(token);
+                    }
+                }
+                _ => {
+                    if let Some((rtt, used)) = rtt(token, next) {
+                        hop.probes.push(Probe {
+                            addr: self.responder.0,
+                            name: self.responder.1.clone(),
+                            rtt: Some(rtt),
+                            ..Probe::default()
+                        });
+                        i += used - 1;
+                        continue;
+                    }
+                    let responder = if let Some(addr) = bracketed(token) {
+                        (Some(addr), None)
+                    } else if let Some(addr) = addr(token) {
+                        // "1.1.1.1 (1.1.1.1)" when the address has no name
+                        if next.and_then(bracketed) == Some(addr) {
+                            i += 1;
+                        }
+                        (Some(addr), None)
+                    } else if let Some(addr) = next.and_then(bracketed) {
+                        i += 1;
+                        (Some(addr), Some(token.to_string()))
+                    } else if next
+                        .and_then(|next| rtt(next, tokens.get(i + 1).copied()))
+                        .is_some()
+                    {
+                        (None, Some(token.to_string()))
+                    } else {
+                        // e.g. "[LOCALHOST]" from tracepath, or "Request timed out." from tracert
+                        continue;
+                    };
+                    // tracert prints who answered after the round trip times
+                    for probe in &mut hop.probes[line_start..] {
+                        if probe.rtt.is_some() && !probe.responded() {
+                            probe.addr = responder.0;
+                            probe.name = responder.1.clone();
+                        }
+                    }
+                    self.responder = responder;
+                }
+            }
+        }
+    }
+}
+
+/// Parses all of the output of a trace.
+pub fn parse(output: &str) -> Vec<Hop> {
+    let mut parser = TracerouteParser::default();
+    let mut hops: Vec<Hop> = output
+        .lines()
+        .filter_map(|line| parser.parse(line))
+        .collect();
+    hops.extend(parser.finish());
+    hops
+}
+
+// "fe80::1%eth0" is printed for link local addresses
+fn addr(token: &str) -> Option<IpAddr> {
+    token.split('%').next()?.parse().ok()
+}
+
+// "(192.168.1.1)", or "[192.168.1.1]" from tracert
+fn bracketed(token: &str) -> Option<IpAddr> {
+    let inner = token
+        .strip_prefix('(')
+        .and_then(|token| token.strip_suffix(')'))
+        .or_else(|| token.strip_prefix('[')?.strip_suffix(']'))?;
+    addr(inner)
+}
+
+// "0.512 ms", "0.512ms" from tracepath or "<1 ms" from tracert, along with how many tokens it
+// took up. Times below the resolution tracert prints are given as that resolution.
+fn rtt(token: &str, next: Option<&str>) -> Option<(Duration, usize)> {
+    let (millis, used) = match token.strip_suffix("ms") {
+        Some(millis) if !millis.is_empty() => (millis, 1),
+        _ if next == Some("ms") => (token, 2),
+        _ => return None,
+    };
+    let millis: f64 = millis.trim_start_matches('<').parse().ok()?;
+    if !millis.is_finite() || millis < 0f64 {
+        return None;
+    }
+    Some((Duration::from_secs_f64(millis / 1_000f64), used))
+}
@@ -0,0 +1,267 @@
+//! Tracks `icmp_seq` numbers to make sense of replies that don't arrive in order.
+//!
+//! Replies that were already answered are flagged as duplicates, and replies that arrive after
+//! their sequence number timed out are flagged as late.
+//!
+//! For `ping` implementations that don't report timeouts, like busybox, the tracker can also
+//! synthesise them. Echo requests are sent every interval, so we know roughly when each
+//! `icmp_seq` should be answered. A sequence number is reported as timed out either when a later
+//! one is answered first (a gap), or when its deadline of one interval plus a grace period has
+//! passed.
+//!
+//! Pingers that send their own probes use [`Outstanding`] instead, which knows exactly when each
+//! one was sent.
+use crate::{PingReply, PingResult};
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long to wait past the interval before giving up on a reply.
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(1);
+
+// How many sequence numbers are remembered for spotting duplicate and late replies
+const HISTORY: usize = 64;
+
+lazy_static! {
+    // "no answer yet for icmp_seq=8" from iputils, "Request timeout for icmp_seq 19" from macOS
+    static ref TIMEOUT_SEQ_RE: Regex = Regex::new(r"icmp_seq[= ](?P<seq>\d+)").unwrap();
+}
+
+pub struct SeqTracker {
+    interval: Duration,
+    grace: Duration,
+    synthesise: bool,
+    first_seq: u64,
+    next_seq: u64,
+    limit: Option<u64>,
+    // When `first_seq` was (or would have been) sent
+    anchor: Instant,
+    answered: VecDeque<u64>,
+    timed_out: VecDeque<u64>,
+    finished: bool,
+}
+
+impl SeqTracker {
+    /// Starts tracking a ping that sends its first request at `now`, numbered from 0, and
+    /// synthesises timeouts for it.
+    pub fn new(interval: Duration, grace: Duration, now: Instant) -> SeqTracker {
+        SeqTracker {
+            interval,
+            grace,
+            synthesise: true,
+            first_seq: 0,
+            next_seq: 0,
+            limit: None,
+            anchor: now,
+            answered: VecDeque::with_capacity(HISTORY),
+            timed_out: VecDeque::with_capacity(HISTORY),
+            finished: false,
+        }
+    }
+
+    /// Tracks a ping that reports its own timeouts, only flagging duplicate and late replies.
+    pub fn reconciling() -> SeqTracker {
+        SeqTracker {
+            synthesise: false,
+            ..SeqTracker::new(Duration::ZERO, Duration::ZERO, Instant::now())
+        }
+    }
+
+    /// The sequence number of the first request, for pings that don't count from 0.
+    pub fn first_seq(mut self, seq: u64) -> SeqTracker {
+        self.first_seq = seq;
+        self.next_seq = seq;
+        self
+    }
+
+    /// Only this many requests will be sent, so no timeouts are reported past them.
+    pub fn count(mut self, count: u32) -> SeqTracker {
+        self.limit = Some(count as u64);
+        self
+    }
+
+    /// Whether [`SeqTracker::tick`] needs calling regularly.
+    pub fn synthesises_timeouts(&self) -> bool {
+        self.synthesise
+    }
+
+    /// Passes on a parsed result, flagging duplicate and late replies. Synthesised timeouts for
+    /// any sequence numbers it skipped come first.
+    pub fn track(&mut self, mut result: PingResult, now: Instant) -> Vec<PingResult> {
+        let seq = match result_seq(&result) {
+            Some(seq) => self.unwrap_seq(seq),
+            None => return vec![result],
+        };
+        // Some pings report timeouts themselves, but only once the next reply is due, so we may
+        // have got there first.
+        if matches!(result, PingResult::Timeout(_)) && self.timed_out.contains(&seq) {
+            return vec![];
+        }
+        let mut results = vec![];
+        if seq >= self.next_seq {
+            if self.synthesise {
+                for skipped in self.next_seq..seq {
+                    results.push(self.timeout(skipped));
+                }
+            }
+            self.next_seq = seq + 1;
+            // Re-anchor on when this request was actually sent, so that we don't drift.
+            if let PingResult::Pong(duration, _) = &result {
+                let elapsed = *duration + self.interval * (seq - self.first_seq) as u32;
+                if let Some(anchor) = now.checked_sub(elapsed) {
+                    self.anchor = anchor;
+                }
+            }
+        }
+        match &mut result {
+            PingResult::Timeout(_) => remember(&mut self.timed_out, seq),
+            PingResult::Pong(_, reply) => {
+                if reply.duplicate || self.answered.contains(&seq) {
+                    reply.duplicate = true;
+                } else {
+                    remember(&mut self.answered, seq);
+                    if let Some(idx) = self.timed_out.iter().position(|s| *s == seq) {
+                        self.timed_out.remove(idx);
+                        reply.late = true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        results.push(result);
+        results
+    }
+
+    /// Reports timeouts for requests whose deadline has passed by `now`.
+    pub fn tick(&mut self, now: Instant) -> Vec<PingResult> {
+        let mut results = vec![];
+        while self.synthesise
+            && !self.finished
+            && self.within_limit()
+            && now >= self.deadline(self.next_seq)
+        {
+            results.push(self.timeout(self.next_seq));
+            self.next_seq += 1;
+        }
+        results
+    }
+
+    /// Stops reporting timeouts, once ping has exited.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    fn within_limit(&self) -> bool {
+        self.limit
+            .is_none_or(|limit| self.next_seq - self.first_seq < limit)
+    }
+
+    fn deadline(&self, seq: u64) -> Instant {
+        self.anchor + self.interval * (seq - self.first_seq) as u32 + self.interval + self.grace
+    }
+
+    fn timeout(&mut self, seq: u64) -> PingResult {
+        remember(&mut self.timed_out, seq);
+        PingResult::Timeout(format!("no answer yet for icmp_seq={}", seq % 65536))
+    }
+
+    // icmp_seq is 16 bits and wraps around, so pick the sequence number closest to the one we
+    // expect next.
+    fn unwrap_seq(&self, seq: u64) -> u64 {
+        let diff = (seq as u16).wrapping_sub(self.next_seq as u16) as i16 as i64;
+        (self.next_seq as i64 + diff).max(0) as u64
+    }
+}
+
+pub(crate) fn result_seq(result: &PingResult) -> Option<u64> {
+    match result {
+        PingResult::Pong(_, reply)
+        | PingResult::Unreachable(reply)
+        | PingResult::TtlExceeded(reply) => reply.seq,
+        PingResult::Timeout(line) => TIMEOUT_SEQ_RE
+            .captures(line)
+            .and_then(|cap| cap["seq"].parse().ok()),
+        _ => None,
+    }
+}
+
+fn remember<T>(seqs: &mut VecDeque<T>, seq: T) {
+    if seqs.len() == HISTORY {
+        seqs.pop_front();
+    }
+    seqs.push_back(seq);
+}
+
+// Keeps track of probes that are waiting for a reply, and the ones that have been dealt with.
+pub(crate) struct Outstanding {
+    waiting: HashMap<u64, Instant>,
+    answered: VecDeque<u64>,
+    // Along with when they were sent, so that late replies still have a round trip time
+    timed_out: VecDeque<(u64, Instant)>,
+    highest: Option<u64>,
+}
+
+impl Outstanding {
+    pub(crate) fn new() -> Outstanding {
+        Outstanding {
+            waiting: HashMap::new(),
+            answered: VecDeque::with_capacity(HISTORY),
+            timed_out: VecDeque::with_capacity(HISTORY),
+            highest: None,
+        }
+    }
+
+    pub(crate) fn sent(&mut self, seq: u64, now: Instant) {
+        self.waiting.insert(seq, now);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiting.is_empty()
+    }
+
+    // When the probe that has been waiting longest was sent
+    pub(crate) fn oldest(&self) -> Option<Instant> {
+        self.waiting.values().min().copied()
+    }
+
+    pub(crate) fn reply(&mut self, seq: u64, now: Instant) -> Option<(Duration, PingReply)> {
+        let mut reply = PingReply::new(String::new());
+        if self.answered.contains(&seq) {
+            reply.duplicate = true;
+            return Some((Duration::ZERO, reply));
+        }
+        let sent = match self.waiting.remove(&seq) {
+            Some(sent) => sent,
+            // Anything else isn't something we sent, or was too long ago to say
+            None => {
+                let (_, sent) = self.timed_out.iter().find(|(late, _)| *late == seq)?;
+                reply.late = true;
+                *sent
+            }
+        };
+        remember(&mut self.answered, seq);
+        reply.reordered = self.highest.is_some_and(|highest| seq < highest);
+        self.highest = self.highest.max(Some(seq));
+        Some((now - sent, reply))
+    }
+
+    // Removes the probes sent before `cutoff`, returning their sequence numbers in order
+    pub(crate) fn expire(&mut self, cutoff: Instant) -> Vec<u64> {
+        let mut expired: Vec<(u64, Instant)> = self
+            .waiting
+            .iter()
+            .filter(|(_, sent)| **sent <= cutoff)
+            .map(|(seq, sent)| (*seq, *sent))
+            .collect();
+        expired.sort_unstable();
+        for (seq, sent) in &expired {
+            self.waiting.remove(seq);
+            remember(&mut self.timed_out, (*seq, *sent));
+        }
+        expired.into_iter().map(|(seq, _)| seq).collect()
+    }
+}
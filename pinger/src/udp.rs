@@ -0,0 +1,197 @@
+//! Measures a path with UDP packets echoed back by `netcheck-reflector`, for networks where ICMP
+//! is treated differently to application traffic.
+//!
+//! Each probe carries a magic number, its sequence number and when it was sent, padded out to
+//! the size option. Probes are sent every interval whether or not the last one was answered, so
+//! besides loss this shows replies arriving out of order, which are marked
+//! [`PingReply::reordered`].
+use crate::tracker::Outstanding;
+use crate::{
+    pick_addr, probe_outstanding, Parser, PingError, PingHandle, PingOptions, PingReply,
+    PingResult, Pinger, ProbeSocket,
+};
+use anyhow::Result;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The port the reflector listens on, and probes are sent to if the target doesn't have one.
+pub const DEFAULT_PORT: u16 = 7047;
+
+const MAGIC: &[u8; 4] = b"NCUE";
+// The magic number, sequence number and timestamp
+const HEADER_SIZE: usize = 20;
+const DEFAULT_SIZE: usize = 64;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A probe, as sent and echoed back.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Probe {
+    pub seq: u64,
+    /// When the probe was sent, in nanoseconds since the Unix epoch.
+    pub sent: u64,
+}
+
+impl Probe {
+    pub fn encode(&self, size: usize) -> Vec<u8> {
+        let mut packet = vec![0u8; size.max(HEADER_SIZE)];
+        packet[..4].copy_from_slice(MAGIC);
+        packet[4..12].copy_from_slice(&self.seq.to_be_bytes());
+        packet[12..20].copy_from_slice(&self.sent.to_be_bytes());
+        packet
+    }
+
+    /// Returns `None` for anything that isn't one of our probes.
+    pub fn decode(packet: &[u8]) -> Option<Probe> {
+        if packet.len() < HEADER_SIZE || &packet[..4] != MAGIC {
+            return None;
+        }
+        let mut seq = [0; 8];
+        seq.copy_from_slice(&packet[4..12]);
+        let mut sent = [0; 8];
+        sent.copy_from_slice(&packet[12..20]);
+        Some(Probe {
+            seq: u64::from_be_bytes(seq),
+            sent: u64::from_be_bytes(sent),
+        })
+    }
+}
+
+/// Echoes probes back to wherever they came from, until receiving fails. Anything that isn't a
+/// probe is dropped, so that the reflector can't be used to bounce other traffic.
+pub fn reflect(socket: &UdpSocket) -> io::Result<()> {
+    let mut buffer = [0; 65536];
+    loop {
+        let (size, from) = socket.recv_from(&mut buffer)?;
+        if Probe::decode(&buffer[..size]).is_some() {
+            // The sender will see this as loss, which is all we could tell it anyway
+            let _ = socket.send_to(&buffer[..size], from);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct UdpPinger {
+    options: PingOptions,
+}
+
+impl UdpPinger {
+    fn resolve(&self, target: &str) -> Result<SocketAddr> {
+        let addrs = match target.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(_) => (target, DEFAULT_PORT)
+                .to_socket_addrs()
+                .map(Iterator::collect)
+                .unwrap_or_else(|_| Vec::new()),
+        };
+        pick_addr(addrs, self.options.ip_version)
+            .ok_or_else(|| PingError::HostnameError(target.to_string()).into())
+    }
+}
+
+fn now_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+struct UdpProbes {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    size: usize,
+    buffer: Vec<u8>,
+}
+
+impl UdpProbes {
+    // The result for a packet of `size` in the buffer, if it's a reply to one of our probes
+    fn reply(
+        &self,
+        size: usize,
+        received: Instant,
+        outstanding: &mut Outstanding,
+    ) -> Option<PingResult> {
+        let seq = Probe::decode(&self.buffer[..size])?.seq;
+        let (duration, reply) = outstanding.reply(seq, received)?;
+        let line = format!(
+            "{} bytes from {}: udp_seq={} time={:.3} ms",
+            size,
+            self.addr,
+            seq,
+            duration.as_micros() as f64 / 1_000f64
+        );
+        Some(PingResult::Pong(
+            duration,
+            PingReply {
+                seq: Some(seq),
+                bytes: Some(size),
+                from: Some(self.addr.ip()),
+                line,
+                ..reply
+            },
+        ))
+    }
+}
+
+impl ProbeSocket for UdpProbes {
+    fn send(&mut self, seq: u64) {
+        let probe = Probe {
+            seq,
+            sent: now_nanos(),
+        };
+        let _ = self.socket.send(&probe.encode(self.size));
+    }
+
+    fn receive(&mut self, outstanding: &mut Outstanding) -> io::Result<Option<PingResult>> {
+        // Errors include the port being unreachable when the reflector isn't running, which is
+        // reported as a timeout once the probe expires
+        let size = self.socket.recv(&mut self.buffer)?;
+        Ok(self.reply(size, Instant::now(), outstanding))
+    }
+
+    fn set_read_timeout(&self, wait: Duration) -> io::Result<()> {
+        self.socket.set_read_timeout(Some(wait))
+    }
+
+    fn timed_out(&self, seq: u64) -> PingResult {
+        PingResult::Timeout(format!("no answer yet for udp_seq={}", seq))
+    }
+}
+
+impl Pinger for UdpPinger {
+    fn start<P>(&self, target: String) -> Result<PingHandle>
+    where
+        P: Parser,
+    {
+        let options = self.options.clone();
+        let addr = self.resolve(&target)?;
+        let timeout = options.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let size = options.size.unwrap_or(DEFAULT_SIZE);
+        let local = match (options.source, addr) {
+            (Some(source), _) => SocketAddr::new(source, 0),
+            (None, SocketAddr::V4(_)) => ([0, 0, 0, 0], 0).into(),
+            (None, SocketAddr::V6(_)) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+
+        Ok(probe_outstanding(
+            options,
+            timeout,
+            UdpProbes {
+                socket,
+                addr,
+                size,
+                buffer: vec![0u8; size.max(HEADER_SIZE) + 1],
+            },
+        ))
+    }
+
+    fn set_interval(&mut self, interval: Duration) {
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
+    }
+}
@@ -1,28 +1,33 @@
-use crate::{Parser, PingError, PingResult, Pinger};
+use crate::{Parser, PingError, PingHandle, PingOptions, PingReply, PingResult, Pinger};
 use anyhow::Result;
 use dns_lookup::lookup_host;
 use regex::Regex;
 use std::net::IpAddr;
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
 use std::thread;
 use std::time::Duration;
 use winping::{Buffer, Pinger as WinPinger};
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"(?ix-u)time=(?P<time>\d+(?:\.\d+)?)ms").unwrap();
+    static ref RE: Regex = Regex::new(
+        r"(?ix-u)(?:from\ (?P<from>[^\s]+?):\ bytes=(?P<bytes>\d+)\ )?time=(?P<time>\d+(?:\.\d+)?)ms(?:\ TTL=(?P<ttl>\d+))?"
+    )
+    .unwrap();
 }
 
 #[derive(Default)]
 pub struct WindowsPinger {
-    interval: Duration,
+    options: PingOptions,
 }
 
 impl Pinger for WindowsPinger {
-    fn start<P>(&self, target: String) -> Result<mpsc::Receiver<PingResult>>
+    fn start<P>(&self, target: String) -> Result<PingHandle>
     where
         P: Parser,
     {
-        let interval = self.interval;
+        let interval = self.options.interval;
+        let count = self.options.count.unwrap_or(u32::MAX);
         let parsed_ip: IpAddr = match target.parse() {
             Err(_) => {
                 let things = lookup_host(target.as_str())?;
@@ -36,17 +41,25 @@ impl Pinger for WindowsPinger {
         }?;
 
         let (tx, rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
 
         thread::spawn(move || {
             let pinger = WinPinger::new().expect("Failed to create a WinPinger instance");
             let mut buffer = Buffer::new();
-            loop {
+            for _ in 0..count {
+                if stopped.load(Ordering::Acquire) {
+                    break;
+                }
                 match pinger.send(parsed_ip.clone(), &mut buffer) {
                     Ok(rtt) => {
                         if tx
                             .send(PingResult::Pong(
                                 Duration::from_millis(rtt as u64),
-                                "".to_string(),
+                                PingReply {
+                                    from: Some(parsed_ip),
+                                    ..PingReply::new("".to_string())
+                                },
                             ))
                             .is_err()
                         {
@@ -64,11 +77,15 @@ impl Pinger for WindowsPinger {
             }
         });
 
-        Ok(rx)
+        Ok(PingHandle::new(rx, stop))
     }
 
     fn set_interval(&mut self, interval: Duration) {
-        self.interval = interval;
+        self.options.interval = interval;
+    }
+
+    fn set_options(&mut self, options: PingOptions) {
+        self.options = options;
     }
 }
 
@@ -76,7 +93,7 @@ impl Pinger for WindowsPinger {
 pub struct WindowsParser {}
 
 impl Parser for WindowsParser {
-    fn parse(&self, line: String) -> Option<PingResult> {
+    fn parse(&mut self, line: String) -> Option<PingResult> {
         if line.contains("timed out") || line.contains("failure") {
             return Some(PingResult::Timeout(line));
         }
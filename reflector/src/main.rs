@@ -0,0 +1,27 @@
+use anyhow::{Context, Result};
+use pinger::udp;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "netcheck-reflector",
+    about = "Echoes the UDP probes sent by netcheck, to measure a path end to end."
+)]
+struct Args {
+    /// Address to listen on
+    #[structopt(short, long, default_value = "0.0.0.0")]
+    address: IpAddr,
+    /// Port to listen on [default: 7047]
+    #[structopt(short, long)]
+    port: Option<u16>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::from_args();
+    let addr = SocketAddr::new(args.address, args.port.unwrap_or(udp::DEFAULT_PORT));
+    let socket = UdpSocket::bind(addr).with_context(|| format!("Could not listen on {}", addr))?;
+    println!("Reflecting probes sent to {}", socket.local_addr()?);
+    udp::reflect(&socket).context("Could not receive probes")?;
+    Ok(())
+}